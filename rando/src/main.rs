@@ -21,6 +21,10 @@ mod smjsondata;
 struct Opt {
     #[structopt(long, parse(from_os_str), default_value = "SuperMetroid.F8DF.sfc")]
     rom: PathBuf,
+
+    /// Labels (.sym or .mlb) used to name enemy routines in enemies.json.
+    #[structopt(long, parse(from_os_str))]
+    symbols: Option<PathBuf>,
 }
 
 struct RoomPlm {
@@ -180,7 +184,14 @@ fn main() -> Result<(), Error> {
     // read the whole file
     f.read_to_end(&mut buffer)?;
 
-    let sm = super_metroid::SuperMetroidData::new(&buffer)?;
+    let mut sm = super_metroid::SuperMetroidData::new(&buffer)?;
+    if let Some(path) = &opt.symbols {
+        let symbols = super_metroid::symbols::SymbolTable::load(path)?;
+        sm.resolve_symbols(&symbols);
+    }
+    let mut enemies: Vec<&super_metroid::Enemy> = sm.enemies.values().collect();
+    enemies.sort_by_key(|e| e.addr);
+    serde_json::to_writer_pretty(File::create("enemies.json")?, &enemies)?;
 
     let mut rooms: Vec<u16> = sm.room_mdb.keys().cloned().collect();
     rooms.sort();
@@ -241,16 +252,62 @@ fn main() -> Result<(), Error> {
             .unwrap();
         let img = r.render_palette()?;
         img.save(format!("tileset/{:02x}_pallete.png", i)).unwrap();
+        let gpl = sm
+            .palettes
+            .get(&set.palette_ptr)
+            .unwrap()
+            .to_gpl(&format!("{:02x}", i));
+        std::fs::write(format!("tileset/{:02x}_palette.gpl", i), gpl)?;
         let img = r.render_tile_table()?;
         img.save(format!("tileset/{:02x}_tile_table.png", i))
             .unwrap();
+        let img = r.render_tile_table_indexed()?;
+        img.save(format!("tileset/{:02x}_tile_table_indexed.png", i))?;
         renderers.push(r);
     }
 
+    std::fs::create_dir_all("world")?;
+    for area in &[
+        super_metroid::Area::Crateria,
+        super_metroid::Area::Brinstar,
+        super_metroid::Area::Norfair,
+        super_metroid::Area::WreckedShip,
+        super_metroid::Area::Maridia,
+        super_metroid::Area::Tourian,
+        super_metroid::Area::Ceres,
+    ] {
+        let options = super_metroid::worldmap::WorldMapOptions {
+            draw_doors: true,
+            ..Default::default()
+        };
+        let img = super_metroid::worldmap::render_area(&sm, *area, &options)?;
+        img.save(format!("world/{:?}.png", area))?;
+    }
+
+    std::fs::create_dir_all("map")?;
+    for map in &sm.area_maps {
+        let img = map.render(&sm.map_tiles, &sm.map_palette, None)?;
+        img.save(format!("map/{:?}.png", map.area))?;
+        for issue in map.check_rooms(&sm) {
+            println!(
+                "{:?} map: room {:04x} at ({}, {}): {:?}",
+                map.area, issue.room_ptr, issue.x, issue.y, issue.issue
+            );
+        }
+    }
+
     let clean_file_re = Regex::new(r"[\./\\ ]").unwrap();
     for (addr, room) in &sm.room_mdb {
         for (i, state) in room.states.iter().enumerate() {
-            let renderer = renderers.get(state.data.tile_set as usize).unwrap();
+            let (animated_tiles, palette_fx) = match sm.fx_for(&state.data, None) {
+                Some(fx) => (
+                    sm.animated_tiles_for(fx),
+                    sm.palette_fx_for(state.data.tile_set, fx),
+                ),
+                None => (Vec::new(), Vec::new()),
+            };
+            let renderer = renderers.get_mut(state.data.tile_set as usize).unwrap();
+            renderer.apply_palette_fx(&palette_fx, None);
             let room_data = &sm.level_data.get(&state.data.level_data).unwrap();
             let img = renderer.render_room(i, room, room_data)?;
 
@@ -260,6 +317,21 @@ fn main() -> Result<(), Error> {
             };
             img.save(format!("room/{:04x}_{}{}.png", addr, i, room_name))
                 .unwrap();
+
+            if !animated_tiles.is_empty() || !palette_fx.is_empty() {
+                let mut renderer =
+                    super_metroid::graphics::TileRenderer::for_tile_set(&sm, state.data.tile_set)?;
+                let frames = renderer.render_room_animation(
+                    i,
+                    room,
+                    room_data,
+                    &animated_tiles,
+                    &palette_fx,
+                    4 * super_metroid::fx::TICKS_PER_SECOND,
+                )?;
+                let f = File::create(format!("room/{:04x}_{}{}.gif", addr, i, room_name))?;
+                super_metroid::fx::write_gif(f, frames)?;
+            }
         }
     }
 
@@ -273,6 +345,9 @@ fn main() -> Result<(), Error> {
     };
     for (addr, room) in &edges.sm.room_mdb {
         for door in &room.door_list {
+            if door.dest_room_ptr == 0 {
+                continue;
+            }
             edges.edges.push((*addr, door.dest_room_ptr));
         }
     }
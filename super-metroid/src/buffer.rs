@@ -0,0 +1,324 @@
+use failure::{format_err, Error};
+use serde::Serialize;
+
+use super::Color;
+
+// Something tiles can be drawn into.
+pub trait TileTarget {
+    // Sets a pixel to entry `index` of `colors`.  Fails if the target can
+    // not show that entry.
+    fn put_color(
+        &mut self,
+        x: usize,
+        y: usize,
+        colors: &[Color],
+        index: usize,
+        transparent: bool,
+    ) -> Result<(), Error>;
+}
+
+// A plain 8 bit RGBA image that all renderers draw into.  Conversions to the
+// `image` crate are available with the `render` feature.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RgbaBuffer {
+    pub width: usize,
+    pub height: usize,
+    // Row major, 4 bytes per pixel.
+    pub pixels: Vec<u8>,
+}
+
+pub const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+impl RgbaBuffer {
+    // Creates a fully transparent buffer.
+    pub fn new(width: usize, height: usize) -> RgbaBuffer {
+        RgbaBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) outside of {}x{} buffer",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        (y * self.width + x) * 4
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = self.offset(x, y);
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[i..i + 4]);
+        pixel
+    }
+
+    pub fn put_pixel(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let i = self.offset(x, y);
+        self.pixels[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    // Draws `other` with its top left corner at (x, y).  Transparent pixels
+    // are skipped and anything past the edges is clipped.  Renderers only
+    // produce fully opaque or fully transparent pixels so there is no
+    // blending.
+    pub fn overlay(&mut self, other: &RgbaBuffer, x: usize, y: usize) {
+        for oy in 0..other.height {
+            if y + oy >= self.height {
+                break;
+            }
+            for ox in 0..other.width {
+                if x + ox >= self.width {
+                    break;
+                }
+                let pixel = other.get_pixel(ox, oy);
+                if pixel[3] != 0 {
+                    self.put_pixel(x + ox, y + oy, pixel);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "render")]
+    pub fn to_image(&self) -> image::RgbaImage {
+        image::RgbaImage::from_raw(self.width as u32, self.height as u32, self.pixels.clone())
+            .expect("buffer size matches its dimensions")
+    }
+
+    #[cfg(feature = "render")]
+    pub fn from_image(img: &image::RgbaImage) -> RgbaBuffer {
+        RgbaBuffer {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            pixels: img.clone().into_raw(),
+        }
+    }
+
+    // Saves the buffer with the format picked from the path's extension.
+    #[cfg(feature = "render")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        self.to_image()
+            .save(path)
+            .map_err(|e| format_err!("failed to save {}: {}", path.display(), e))
+    }
+}
+
+impl TileTarget for RgbaBuffer {
+    fn put_color(
+        &mut self,
+        x: usize,
+        y: usize,
+        colors: &[Color],
+        index: usize,
+        transparent: bool,
+    ) -> Result<(), Error> {
+        let color = colors
+            .get(index)
+            .ok_or_else(|| format_err!("color {} is past the end of the palette", index))?;
+        let alpha = if transparent { 0x0 } else { 0xff };
+        self.put_pixel(x, y, [color.r, color.g, color.b, alpha]);
+        Ok(())
+    }
+}
+
+// An 8 bit indexed image with its palette.  Rendered tiles use index
+// palette_row * 16 + color, so the SNES palette entry of every pixel is kept.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexedBuffer {
+    pub width: usize,
+    pub height: usize,
+    // Row major, 1 byte per pixel.
+    pub pixels: Vec<u8>,
+    pub palette: Vec<Color>,
+}
+
+impl IndexedBuffer {
+    // Creates a buffer filled with index 0.
+    pub fn new(width: usize, height: usize, palette: &[Color]) -> IndexedBuffer {
+        IndexedBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            palette: palette.to_vec(),
+        }
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) outside of {}x{} buffer",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        y * self.width + x
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[self.offset(x, y)]
+    }
+
+    pub fn put_pixel(&mut self, x: usize, y: usize, index: u8) {
+        let i = self.offset(x, y);
+        self.pixels[i] = index;
+    }
+
+    // Converts to RGBA.  The first color of each 16 color row is
+    // transparent as it is on the SNES.
+    pub fn to_rgba(&self) -> RgbaBuffer {
+        let mut img = RgbaBuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = self.get_pixel(x, y) as usize;
+                if index.is_multiple_of(16) {
+                    continue;
+                }
+                if let Some(color) = self.palette.get(index) {
+                    img.put_pixel(x, y, [color.r, color.g, color.b, 0xff]);
+                }
+            }
+        }
+        img
+    }
+
+    // Writes an 8 bit indexed PNG with the palette embedded.  The first
+    // color of each row is marked transparent.
+    #[cfg(feature = "render")]
+    pub fn write_png<W: std::io::Write>(&self, w: W) -> Result<(), Error> {
+        if self.palette.is_empty() || self.palette.len() > 256 {
+            return Err(format_err!(
+                "indexed PNGs need 1 to 256 colors, got {}",
+                self.palette.len()
+            ));
+        }
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+
+        let plte: Vec<u8> = self
+            .palette
+            .iter()
+            .flat_map(|c| vec![c.r, c.g, c.b])
+            .collect();
+        writer.write_chunk(png::chunk::PLTE, &plte)?;
+        let trns: Vec<u8> = (0..self.palette.len())
+            .map(|i| if i % 16 == 0 { 0x00 } else { 0xff })
+            .collect();
+        writer.write_chunk(png::chunk::tRNS, &trns)?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+
+    // Reads an 8 bit indexed PNG such as one written by `write_png`.
+    #[cfg(feature = "render")]
+    pub fn read_png<R: std::io::Read>(r: R) -> Result<IndexedBuffer, Error> {
+        let mut decoder = png::Decoder::new(r);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info()?;
+        if info.color_type != png::ColorType::Indexed || info.bit_depth != png::BitDepth::Eight {
+            return Err(format_err!(
+                "expected an 8 bit indexed PNG, got {:?} {:?}",
+                info.color_type,
+                info.bit_depth
+            ));
+        }
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels)?;
+        let palette = reader
+            .info()
+            .palette
+            .as_ref()
+            .ok_or_else(|| format_err!("indexed PNG has no palette"))?
+            .chunks_exact(3)
+            .map(|c| Color {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+            })
+            .collect();
+
+        Ok(IndexedBuffer {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+            palette,
+        })
+    }
+
+    #[cfg(feature = "render")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let f = std::fs::File::create(path)?;
+        self.write_png(std::io::BufWriter::new(f))
+    }
+}
+
+impl TileTarget for IndexedBuffer {
+    fn put_color(
+        &mut self,
+        x: usize,
+        y: usize,
+        _colors: &[Color],
+        index: usize,
+        _: bool,
+    ) -> Result<(), Error> {
+        if index > 0xff {
+            return Err(format_err!(
+                "color {} does not fit in an indexed image",
+                index
+            ));
+        }
+        self.put_pixel(x, y, index as u8);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_skips_transparent_and_clips() {
+        let mut dest = RgbaBuffer::new(3, 2);
+        dest.put_pixel(1, 1, [9, 9, 9, 0xff]);
+
+        let mut src = RgbaBuffer::new(2, 2);
+        src.put_pixel(0, 0, [1, 2, 3, 0xff]);
+        src.put_pixel(1, 0, [4, 5, 6, 0xff]);
+        dest.overlay(&src, 2, 1);
+        dest.overlay(&src, 0, 0);
+
+        assert_eq!(dest.get_pixel(0, 0), [1, 2, 3, 0xff]);
+        assert_eq!(dest.get_pixel(1, 0), [4, 5, 6, 0xff]);
+        // Transparent source pixels leave the destination alone.
+        assert_eq!(dest.get_pixel(1, 1), [9, 9, 9, 0xff]);
+        assert_eq!(dest.get_pixel(2, 1), [1, 2, 3, 0xff]);
+        assert_eq!(dest.get_pixel(2, 0), TRANSPARENT);
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn indexed_png_round_trips() {
+        let palette: Vec<Color> = (0..32u16).map(|i| Color::from_bgr555(i * 0x421)).collect();
+        let mut img = IndexedBuffer::new(3, 2, &palette);
+        img.put_pixel(0, 0, 0x11);
+        img.put_pixel(2, 1, 0x1f);
+
+        let mut png = Vec::new();
+        img.write_png(&mut png).unwrap();
+        let read = IndexedBuffer::read_png(&png[..]).unwrap();
+        assert_eq!(read, img);
+
+        let rgba = read.to_rgba();
+        assert_eq!(rgba.get_pixel(0, 0), [0x88, 0x88, 0x88, 0xff]);
+        assert_eq!(rgba.get_pixel(1, 0), TRANSPARENT);
+    }
+}
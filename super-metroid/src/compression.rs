@@ -17,6 +17,22 @@ enum Op {
     ExtendedCmd = 0x7,
 }
 
+// Returns the byte at `addr` in the output so far.  Copies can reference
+// bytes they are themselves producing so this is checked on each byte.
+fn out_byte(out: &[u8], addr: usize) -> Result<u8, Error> {
+    out.get(addr)
+        .cloned()
+        .ok_or_else(|| format_err!("copy from {:x} past end of output", addr))
+}
+
+// Returns the start of a copy that is `offset` bytes back from the end of
+// the output.
+fn relative_addr(out: &[u8], offset: u8) -> Result<usize, Error> {
+    out.len()
+        .checked_sub(offset as usize)
+        .ok_or_else(|| format_err!("copy offset {:x} before start of output", offset))
+}
+
 pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
     // Algorithm from http://patrickjohnston.org/ASM/ROM%20data/Super%20Metroid/decompress.py
     // and https://www.romhacking.net/documents/243/
@@ -90,7 +106,7 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
                 // specified by the next word in the stream.
                 let addr = r.read_u16::<LittleEndian>()? as usize;
                 for i in 0..size {
-                    out.push(out[addr + i]);
+                    out.push(out_byte(&out, addr + i)?);
                 }
             }
             Op::XorCopy => {
@@ -98,24 +114,24 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
                 // as they are copied.
                 let addr = r.read_u16::<LittleEndian>()? as usize;
                 for i in 0..size {
-                    out.push(out[addr + i] ^ 0xff);
+                    out.push(out_byte(&out, addr + i)? ^ 0xff);
                 }
             }
             Op::SubtractCopy => {
                 // the next byte in the stream an offset from the end of the
                 // current decompression output.  <size> bytes are copied from
                 // that offset.
-                let addr = out.len() - r.read_u8()? as usize;
+                let addr = relative_addr(&out, r.read_u8()?)?;
                 for i in 0..size {
-                    out.push(out[addr + i]);
+                    out.push(out_byte(&out, addr + i)?);
                 }
             }
             Op::ExtendedCmd => {
                 // According to the python implementation this works like a
                 // combination of SubtractCopy and XorCopy.
-                let addr = out.len() - r.read_u8()? as usize;
+                let addr = relative_addr(&out, r.read_u8()?)?;
                 for i in 0..size {
-                    out.push(out[addr + i] ^ 0xff);
+                    out.push(out_byte(&out, addr + i)? ^ 0xff);
                 }
             }
         }
@@ -192,6 +208,12 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bad_copy_is_an_error() {
+        assert!(decompress(&[0x82, 0x01, 0x00, 0xff]).is_err());
+        assert!(decompress(&[0x20, 0x1, 0xc2, 0x03, 0xff]).is_err());
+    }
+
     #[test]
     fn test_extended_and_subtract_xor_copy_ops() {
         assert_eq!(
@@ -0,0 +1,742 @@
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::symbols::SymbolTable;
+use super::{DoorData, RoomMdb};
+
+// Bank holding room setup, room main and door ASM.
+const ROOM_ASM_BANK: u8 = 0x8f;
+// Stops runaway disassembly of data that isn't code.
+const MAX_INSTRUCTIONS: usize = 0x1000;
+
+// A 24 bit address in the SNES's memory map.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct SnesAddr(pub u32);
+
+impl SnesAddr {
+    pub fn new(bank: u8, offset: u16) -> SnesAddr {
+        SnesAddr((bank as u32) << 16 | offset as u32)
+    }
+
+    pub fn bank(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn offset(self) -> u16 {
+        self.0 as u16
+    }
+
+    // Offset into a LoROM image.  None for addresses outside of ROM.
+    pub fn to_rom_offset(self) -> Option<usize> {
+        if self.offset() < 0x8000 || self.bank() == 0x7e || self.bank() == 0x7f {
+            return None;
+        }
+        Some(((self.bank() as usize & 0x7f) << 15) | (self.offset() as usize - 0x8000))
+    }
+
+    // Adds to the offset, wrapping within the bank like the program counter
+    // does.
+    pub fn wrapping_add(self, n: u16) -> SnesAddr {
+        SnesAddr::new(self.bank(), self.offset().wrapping_add(n))
+    }
+}
+
+impl fmt::Display for SnesAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:02x}:{:04x}", self.bank(), self.offset())
+    }
+}
+
+// Processor status bits that change instruction lengths.  `true` means the
+// flag is set and the registers are 8 bit.  Super Metroid runs almost
+// everything with 16 bit registers, the default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Flags {
+    pub m: bool,
+    pub x: bool,
+}
+
+// REP and SEP operand bits.
+const FLAG_M: u32 = 0x20;
+const FLAG_X: u32 = 0x10;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    // Always 8 bit.  Used by REP, SEP and the signature bytes of BRK and
+    // COP.
+    Immediate8,
+    // Sized by the M flag.
+    ImmediateM,
+    // Sized by the X flag.
+    ImmediateX,
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndirectX,
+    DirectIndirectY,
+    DirectIndirectLong,
+    DirectIndirectLongY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    AbsoluteIndirect,
+    AbsoluteIndexedIndirect,
+    AbsoluteIndirectLong,
+    StackRelative,
+    StackRelativeIndirectY,
+    Relative,
+    RelativeLong,
+    BlockMove,
+}
+
+impl Mode {
+    pub fn operand_len(self, flags: Flags) -> usize {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::ImmediateM => {
+                if flags.m {
+                    1
+                } else {
+                    2
+                }
+            }
+            Mode::ImmediateX => {
+                if flags.x {
+                    1
+                } else {
+                    2
+                }
+            }
+            Mode::Immediate8
+            | Mode::Direct
+            | Mode::DirectX
+            | Mode::DirectY
+            | Mode::DirectIndirect
+            | Mode::DirectIndirectX
+            | Mode::DirectIndirectY
+            | Mode::DirectIndirectLong
+            | Mode::DirectIndirectLongY
+            | Mode::StackRelative
+            | Mode::StackRelativeIndirectY
+            | Mode::Relative => 1,
+            Mode::Absolute
+            | Mode::AbsoluteX
+            | Mode::AbsoluteY
+            | Mode::AbsoluteIndirect
+            | Mode::AbsoluteIndexedIndirect
+            | Mode::AbsoluteIndirectLong
+            | Mode::RelativeLong
+            | Mode::BlockMove => 2,
+            Mode::AbsoluteLong | Mode::AbsoluteLongX => 3,
+        }
+    }
+}
+
+// Mnemonic and addressing mode of each opcode.
+const OPCODES: [(&str, Mode); 256] = [
+    // 0x00
+    ("BRK", Mode::Immediate8),
+    ("ORA", Mode::DirectIndirectX),
+    ("COP", Mode::Immediate8),
+    ("ORA", Mode::StackRelative),
+    ("TSB", Mode::Direct),
+    ("ORA", Mode::Direct),
+    ("ASL", Mode::Direct),
+    ("ORA", Mode::DirectIndirectLong),
+    ("PHP", Mode::Implied),
+    ("ORA", Mode::ImmediateM),
+    ("ASL", Mode::Accumulator),
+    ("PHD", Mode::Implied),
+    ("TSB", Mode::Absolute),
+    ("ORA", Mode::Absolute),
+    ("ASL", Mode::Absolute),
+    ("ORA", Mode::AbsoluteLong),
+    // 0x10
+    ("BPL", Mode::Relative),
+    ("ORA", Mode::DirectIndirectY),
+    ("ORA", Mode::DirectIndirect),
+    ("ORA", Mode::StackRelativeIndirectY),
+    ("TRB", Mode::Direct),
+    ("ORA", Mode::DirectX),
+    ("ASL", Mode::DirectX),
+    ("ORA", Mode::DirectIndirectLongY),
+    ("CLC", Mode::Implied),
+    ("ORA", Mode::AbsoluteY),
+    ("INC", Mode::Accumulator),
+    ("TCS", Mode::Implied),
+    ("TRB", Mode::Absolute),
+    ("ORA", Mode::AbsoluteX),
+    ("ASL", Mode::AbsoluteX),
+    ("ORA", Mode::AbsoluteLongX),
+    // 0x20
+    ("JSR", Mode::Absolute),
+    ("AND", Mode::DirectIndirectX),
+    ("JSL", Mode::AbsoluteLong),
+    ("AND", Mode::StackRelative),
+    ("BIT", Mode::Direct),
+    ("AND", Mode::Direct),
+    ("ROL", Mode::Direct),
+    ("AND", Mode::DirectIndirectLong),
+    ("PLP", Mode::Implied),
+    ("AND", Mode::ImmediateM),
+    ("ROL", Mode::Accumulator),
+    ("PLD", Mode::Implied),
+    ("BIT", Mode::Absolute),
+    ("AND", Mode::Absolute),
+    ("ROL", Mode::Absolute),
+    ("AND", Mode::AbsoluteLong),
+    // 0x30
+    ("BMI", Mode::Relative),
+    ("AND", Mode::DirectIndirectY),
+    ("AND", Mode::DirectIndirect),
+    ("AND", Mode::StackRelativeIndirectY),
+    ("BIT", Mode::DirectX),
+    ("AND", Mode::DirectX),
+    ("ROL", Mode::DirectX),
+    ("AND", Mode::DirectIndirectLongY),
+    ("SEC", Mode::Implied),
+    ("AND", Mode::AbsoluteY),
+    ("DEC", Mode::Accumulator),
+    ("TSC", Mode::Implied),
+    ("BIT", Mode::AbsoluteX),
+    ("AND", Mode::AbsoluteX),
+    ("ROL", Mode::AbsoluteX),
+    ("AND", Mode::AbsoluteLongX),
+    // 0x40
+    ("RTI", Mode::Implied),
+    ("EOR", Mode::DirectIndirectX),
+    ("WDM", Mode::Immediate8),
+    ("EOR", Mode::StackRelative),
+    ("MVP", Mode::BlockMove),
+    ("EOR", Mode::Direct),
+    ("LSR", Mode::Direct),
+    ("EOR", Mode::DirectIndirectLong),
+    ("PHA", Mode::Implied),
+    ("EOR", Mode::ImmediateM),
+    ("LSR", Mode::Accumulator),
+    ("PHK", Mode::Implied),
+    ("JMP", Mode::Absolute),
+    ("EOR", Mode::Absolute),
+    ("LSR", Mode::Absolute),
+    ("EOR", Mode::AbsoluteLong),
+    // 0x50
+    ("BVC", Mode::Relative),
+    ("EOR", Mode::DirectIndirectY),
+    ("EOR", Mode::DirectIndirect),
+    ("EOR", Mode::StackRelativeIndirectY),
+    ("MVN", Mode::BlockMove),
+    ("EOR", Mode::DirectX),
+    ("LSR", Mode::DirectX),
+    ("EOR", Mode::DirectIndirectLongY),
+    ("CLI", Mode::Implied),
+    ("EOR", Mode::AbsoluteY),
+    ("PHY", Mode::Implied),
+    ("TCD", Mode::Implied),
+    ("JML", Mode::AbsoluteLong),
+    ("EOR", Mode::AbsoluteX),
+    ("LSR", Mode::AbsoluteX),
+    ("EOR", Mode::AbsoluteLongX),
+    // 0x60
+    ("RTS", Mode::Implied),
+    ("ADC", Mode::DirectIndirectX),
+    ("PER", Mode::RelativeLong),
+    ("ADC", Mode::StackRelative),
+    ("STZ", Mode::Direct),
+    ("ADC", Mode::Direct),
+    ("ROR", Mode::Direct),
+    ("ADC", Mode::DirectIndirectLong),
+    ("PLA", Mode::Implied),
+    ("ADC", Mode::ImmediateM),
+    ("ROR", Mode::Accumulator),
+    ("RTL", Mode::Implied),
+    ("JMP", Mode::AbsoluteIndirect),
+    ("ADC", Mode::Absolute),
+    ("ROR", Mode::Absolute),
+    ("ADC", Mode::AbsoluteLong),
+    // 0x70
+    ("BVS", Mode::Relative),
+    ("ADC", Mode::DirectIndirectY),
+    ("ADC", Mode::DirectIndirect),
+    ("ADC", Mode::StackRelativeIndirectY),
+    ("STZ", Mode::DirectX),
+    ("ADC", Mode::DirectX),
+    ("ROR", Mode::DirectX),
+    ("ADC", Mode::DirectIndirectLongY),
+    ("SEI", Mode::Implied),
+    ("ADC", Mode::AbsoluteY),
+    ("PLY", Mode::Implied),
+    ("TDC", Mode::Implied),
+    ("JMP", Mode::AbsoluteIndexedIndirect),
+    ("ADC", Mode::AbsoluteX),
+    ("ROR", Mode::AbsoluteX),
+    ("ADC", Mode::AbsoluteLongX),
+    // 0x80
+    ("BRA", Mode::Relative),
+    ("STA", Mode::DirectIndirectX),
+    ("BRL", Mode::RelativeLong),
+    ("STA", Mode::StackRelative),
+    ("STY", Mode::Direct),
+    ("STA", Mode::Direct),
+    ("STX", Mode::Direct),
+    ("STA", Mode::DirectIndirectLong),
+    ("DEY", Mode::Implied),
+    ("BIT", Mode::ImmediateM),
+    ("TXA", Mode::Implied),
+    ("PHB", Mode::Implied),
+    ("STY", Mode::Absolute),
+    ("STA", Mode::Absolute),
+    ("STX", Mode::Absolute),
+    ("STA", Mode::AbsoluteLong),
+    // 0x90
+    ("BCC", Mode::Relative),
+    ("STA", Mode::DirectIndirectY),
+    ("STA", Mode::DirectIndirect),
+    ("STA", Mode::StackRelativeIndirectY),
+    ("STY", Mode::DirectX),
+    ("STA", Mode::DirectX),
+    ("STX", Mode::DirectY),
+    ("STA", Mode::DirectIndirectLongY),
+    ("TYA", Mode::Implied),
+    ("STA", Mode::AbsoluteY),
+    ("TXS", Mode::Implied),
+    ("TXY", Mode::Implied),
+    ("STZ", Mode::Absolute),
+    ("STA", Mode::AbsoluteX),
+    ("STZ", Mode::AbsoluteX),
+    ("STA", Mode::AbsoluteLongX),
+    // 0xa0
+    ("LDY", Mode::ImmediateX),
+    ("LDA", Mode::DirectIndirectX),
+    ("LDX", Mode::ImmediateX),
+    ("LDA", Mode::StackRelative),
+    ("LDY", Mode::Direct),
+    ("LDA", Mode::Direct),
+    ("LDX", Mode::Direct),
+    ("LDA", Mode::DirectIndirectLong),
+    ("TAY", Mode::Implied),
+    ("LDA", Mode::ImmediateM),
+    ("TAX", Mode::Implied),
+    ("PLB", Mode::Implied),
+    ("LDY", Mode::Absolute),
+    ("LDA", Mode::Absolute),
+    ("LDX", Mode::Absolute),
+    ("LDA", Mode::AbsoluteLong),
+    // 0xb0
+    ("BCS", Mode::Relative),
+    ("LDA", Mode::DirectIndirectY),
+    ("LDA", Mode::DirectIndirect),
+    ("LDA", Mode::StackRelativeIndirectY),
+    ("LDY", Mode::DirectX),
+    ("LDA", Mode::DirectX),
+    ("LDX", Mode::DirectY),
+    ("LDA", Mode::DirectIndirectLongY),
+    ("CLV", Mode::Implied),
+    ("LDA", Mode::AbsoluteY),
+    ("TSX", Mode::Implied),
+    ("TYX", Mode::Implied),
+    ("LDY", Mode::AbsoluteX),
+    ("LDA", Mode::AbsoluteX),
+    ("LDX", Mode::AbsoluteY),
+    ("LDA", Mode::AbsoluteLongX),
+    // 0xc0
+    ("CPY", Mode::ImmediateX),
+    ("CMP", Mode::DirectIndirectX),
+    ("REP", Mode::Immediate8),
+    ("CMP", Mode::StackRelative),
+    ("CPY", Mode::Direct),
+    ("CMP", Mode::Direct),
+    ("DEC", Mode::Direct),
+    ("CMP", Mode::DirectIndirectLong),
+    ("INY", Mode::Implied),
+    ("CMP", Mode::ImmediateM),
+    ("DEX", Mode::Implied),
+    ("WAI", Mode::Implied),
+    ("CPY", Mode::Absolute),
+    ("CMP", Mode::Absolute),
+    ("DEC", Mode::Absolute),
+    ("CMP", Mode::AbsoluteLong),
+    // 0xd0
+    ("BNE", Mode::Relative),
+    ("CMP", Mode::DirectIndirectY),
+    ("CMP", Mode::DirectIndirect),
+    ("CMP", Mode::StackRelativeIndirectY),
+    ("PEI", Mode::DirectIndirect),
+    ("CMP", Mode::DirectX),
+    ("DEC", Mode::DirectX),
+    ("CMP", Mode::DirectIndirectLongY),
+    ("CLD", Mode::Implied),
+    ("CMP", Mode::AbsoluteY),
+    ("PHX", Mode::Implied),
+    ("STP", Mode::Implied),
+    ("JML", Mode::AbsoluteIndirectLong),
+    ("CMP", Mode::AbsoluteX),
+    ("DEC", Mode::AbsoluteX),
+    ("CMP", Mode::AbsoluteLongX),
+    // 0xe0
+    ("CPX", Mode::ImmediateX),
+    ("SBC", Mode::DirectIndirectX),
+    ("SEP", Mode::Immediate8),
+    ("SBC", Mode::StackRelative),
+    ("CPX", Mode::Direct),
+    ("SBC", Mode::Direct),
+    ("INC", Mode::Direct),
+    ("SBC", Mode::DirectIndirectLong),
+    ("INX", Mode::Implied),
+    ("SBC", Mode::ImmediateM),
+    ("NOP", Mode::Implied),
+    ("XBA", Mode::Implied),
+    ("CPX", Mode::Absolute),
+    ("SBC", Mode::Absolute),
+    ("INC", Mode::Absolute),
+    ("SBC", Mode::AbsoluteLong),
+    // 0xf0
+    ("BEQ", Mode::Relative),
+    ("SBC", Mode::DirectIndirectY),
+    ("SBC", Mode::DirectIndirect),
+    ("SBC", Mode::StackRelativeIndirectY),
+    ("PEA", Mode::Absolute),
+    ("SBC", Mode::DirectX),
+    ("INC", Mode::DirectX),
+    ("SBC", Mode::DirectIndirectLongY),
+    ("SED", Mode::Implied),
+    ("SBC", Mode::AbsoluteY),
+    ("PLX", Mode::Implied),
+    ("XCE", Mode::Implied),
+    ("JSR", Mode::AbsoluteIndexedIndirect),
+    ("SBC", Mode::AbsoluteX),
+    ("INC", Mode::AbsoluteX),
+    ("SBC", Mode::AbsoluteLongX),
+];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Instruction {
+    pub addr: SnesAddr,
+    // Opcode followed by the operand.
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    // Flags the instruction was decoded with.
+    pub flags: Flags,
+}
+
+impl Instruction {
+    // Decodes the instruction at `addr` in a LoROM image.
+    pub fn decode(rom: &[u8], addr: SnesAddr, flags: Flags) -> Result<Instruction, Error> {
+        let read = |addr: SnesAddr| -> Result<u8, Error> {
+            addr.to_rom_offset()
+                .and_then(|offset| rom.get(offset))
+                .cloned()
+                .ok_or_else(|| format_err!("{} is not in ROM", addr))
+        };
+
+        let opcode = read(addr)?;
+        let (mnemonic, mode) = OPCODES[opcode as usize];
+        let mut bytes = vec![opcode];
+        for i in 0..mode.operand_len(flags) {
+            bytes.push(read(addr.wrapping_add(i as u16 + 1))?);
+        }
+        Ok(Instruction {
+            addr,
+            bytes,
+            mnemonic,
+            mode,
+            flags,
+        })
+    }
+
+    // The operand as a little endian number.
+    pub fn operand(&self) -> u32 {
+        self.bytes[1..]
+            .iter()
+            .rev()
+            .fold(0, |acc, b| acc << 8 | *b as u32)
+    }
+
+    pub fn next_addr(&self) -> SnesAddr {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+
+    // Destination of a branch, jump or call whose target is known without
+    // running the code.
+    pub fn target(&self) -> Option<SnesAddr> {
+        let operand = self.operand();
+        match (self.mode, self.mnemonic) {
+            (Mode::Relative, _) => Some(self.next_addr().wrapping_add(operand as i8 as u16)),
+            (Mode::RelativeLong, _) => Some(self.next_addr().wrapping_add(operand as u16)),
+            (Mode::Absolute, "JSR") | (Mode::Absolute, "JMP") => {
+                Some(SnesAddr::new(self.addr.bank(), operand as u16))
+            }
+            (Mode::AbsoluteLong, "JSL") | (Mode::AbsoluteLong, "JML") => Some(SnesAddr(operand)),
+            _ => None,
+        }
+    }
+
+    // True if execution never continues with the next instruction.
+    fn ends_path(&self) -> bool {
+        matches!(
+            self.mnemonic,
+            "RTS" | "RTL" | "RTI" | "STP" | "BRK" | "JMP" | "JML" | "BRA" | "BRL"
+        )
+    }
+
+    // Formats the operand, naming targets with `label`.
+    fn operand_text(&self, label: &dyn Fn(SnesAddr) -> Option<String>) -> String {
+        let operand = self.operand();
+        if let Some(target) = self.target() {
+            if let Some(name) = label(target) {
+                return name;
+            }
+        }
+        let imm = |len: usize| match len {
+            1 => format!("#${:02x}", operand),
+            _ => format!("#${:04x}", operand),
+        };
+        match self.mode {
+            Mode::Implied => String::new(),
+            Mode::Accumulator => "A".to_string(),
+            Mode::Immediate8 | Mode::ImmediateM | Mode::ImmediateX => {
+                imm(self.mode.operand_len(self.flags))
+            }
+            Mode::Direct => format!("${:02x}", operand),
+            Mode::DirectX => format!("${:02x},X", operand),
+            Mode::DirectY => format!("${:02x},Y", operand),
+            Mode::DirectIndirect => format!("(${:02x})", operand),
+            Mode::DirectIndirectX => format!("(${:02x},X)", operand),
+            Mode::DirectIndirectY => format!("(${:02x}),Y", operand),
+            Mode::DirectIndirectLong => format!("[${:02x}]", operand),
+            Mode::DirectIndirectLongY => format!("[${:02x}],Y", operand),
+            Mode::Absolute => format!("${:04x}", operand),
+            Mode::AbsoluteX => format!("${:04x},X", operand),
+            Mode::AbsoluteY => format!("${:04x},Y", operand),
+            Mode::AbsoluteLong => format!("${:06x}", operand),
+            Mode::AbsoluteLongX => format!("${:06x},X", operand),
+            Mode::AbsoluteIndirect => format!("(${:04x})", operand),
+            Mode::AbsoluteIndexedIndirect => format!("(${:04x},X)", operand),
+            Mode::AbsoluteIndirectLong => format!("[${:04x}]", operand),
+            Mode::StackRelative => format!("${:02x},S", operand),
+            Mode::StackRelativeIndirectY => format!("(${:02x},S),Y", operand),
+            Mode::Relative | Mode::RelativeLong => {
+                format!("${:04x}", self.target().unwrap().offset())
+            }
+            // The destination bank comes first in the encoding.
+            Mode::BlockMove => format!("${:02x},${:02x}", operand >> 8, operand & 0xff),
+        }
+    }
+}
+
+// A disassembled routine.
+#[derive(Clone, Debug)]
+pub struct Listing {
+    pub entry: SnesAddr,
+    // Sorted by address.
+    pub instructions: Vec<Instruction>,
+    // Names for the entry point, branch targets in the routine and any
+    // other target that has a symbol.
+    pub labels: BTreeMap<SnesAddr, String>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = |addr: SnesAddr| self.labels.get(&addr).cloned();
+        for inst in &self.instructions {
+            if let Some(name) = self.labels.get(&inst.addr) {
+                writeln!(f, "{}:", name)?;
+            }
+            let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let text = format!("{} {}", inst.mnemonic, inst.operand_text(&label));
+            writeln!(
+                f,
+                "  {}  {:<12} {}",
+                inst.addr,
+                bytes.join(" "),
+                text.trim_end()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn default_label(addr: SnesAddr) -> String {
+    format!("loc_{:02x}{:04x}", addr.bank(), addr.offset())
+}
+
+// Disassembles the routine at `entry`, following branches until every path
+// ends in a return or a jump.  Calls are not followed.  REP and SEP update
+// the flags used to size immediate operands.
+pub fn disassemble(
+    rom: &[u8],
+    entry: SnesAddr,
+    flags: Flags,
+    symbols: Option<&SymbolTable>,
+) -> Result<Listing, Error> {
+    let symbol = |addr: SnesAddr| symbols.and_then(|s| s.lookup(addr.0)).map(String::from);
+
+    let mut instructions: BTreeMap<SnesAddr, Instruction> = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    labels.insert(entry, symbol(entry).unwrap_or_else(|| default_label(entry)));
+
+    let mut pending = vec![(entry, flags)];
+    while let Some((mut addr, mut flags)) = pending.pop() {
+        while !instructions.contains_key(&addr) {
+            if instructions.len() >= MAX_INSTRUCTIONS {
+                return Err(format_err!(
+                    "routine at {} is over {} instructions",
+                    entry,
+                    MAX_INSTRUCTIONS
+                ));
+            }
+            let inst = Instruction::decode(rom, addr, flags)?;
+            match inst.mnemonic {
+                "REP" => {
+                    flags.m &= inst.operand() & FLAG_M == 0;
+                    flags.x &= inst.operand() & FLAG_X == 0;
+                }
+                "SEP" => {
+                    flags.m |= inst.operand() & FLAG_M != 0;
+                    flags.x |= inst.operand() & FLAG_X != 0;
+                }
+                _ => (),
+            }
+
+            if let Some(target) = inst.target() {
+                let local = inst.mode == Mode::Relative || inst.mnemonic == "BRL";
+                if local {
+                    labels
+                        .entry(target)
+                        .or_insert_with(|| symbol(target).unwrap_or_else(|| default_label(target)));
+                    pending.push((target, flags));
+                } else if let Some(name) = symbol(target) {
+                    labels.insert(target, name);
+                }
+            }
+
+            let ends_path = inst.ends_path();
+            let next = inst.next_addr();
+            instructions.insert(addr, inst);
+            if ends_path {
+                break;
+            }
+            addr = next;
+        }
+    }
+
+    Ok(Listing {
+        entry,
+        instructions: instructions.into_values().collect(),
+        labels,
+    })
+}
+
+fn disassemble_room_asm(
+    rom: &[u8],
+    ptr: u16,
+    symbols: Option<&SymbolTable>,
+) -> Result<Option<Listing>, Error> {
+    if ptr == 0x0000 {
+        return Ok(None);
+    }
+    disassemble(
+        rom,
+        SnesAddr::new(ROOM_ASM_BANK, ptr),
+        Flags::default(),
+        symbols,
+    )
+    .map(Some)
+}
+
+impl RoomMdb {
+    // Disassembles the routine run when `state` is loaded.
+    pub fn disassemble_setup_asm(
+        &self,
+        state: usize,
+        rom: &[u8],
+        symbols: Option<&SymbolTable>,
+    ) -> Result<Option<Listing>, Error> {
+        let state = self
+            .states
+            .get(state)
+            .ok_or_else(|| format_err!("room has no state {}", state))?;
+        disassemble_room_asm(rom, state.data.setup_asm_ptr, symbols)
+    }
+
+    // Disassembles the routine run every frame while in `state`.
+    pub fn disassemble_main_asm(
+        &self,
+        state: usize,
+        rom: &[u8],
+        symbols: Option<&SymbolTable>,
+    ) -> Result<Option<Listing>, Error> {
+        let state = self
+            .states
+            .get(state)
+            .ok_or_else(|| format_err!("room has no state {}", state))?;
+        disassemble_room_asm(rom, state.data.main_asm_ptr, symbols)
+    }
+}
+
+impl DoorData {
+    // Disassembles the routine run when going through the door.
+    pub fn disassemble_asm(
+        &self,
+        rom: &[u8],
+        symbols: Option<&SymbolTable>,
+    ) -> Result<Option<Listing>, Error> {
+        disassemble_room_asm(rom, self.asm_ptr, symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        let addr = SnesAddr::new(0x8f, 0x91f8);
+        assert_eq!(addr.to_rom_offset(), Some(crate::rom_addr!(0x8f, 0x91f8)));
+        assert_eq!(SnesAddr::new(0x0f, 0x91f8).to_rom_offset(), Some(0x791f8));
+        assert_eq!(SnesAddr::new(0x7e, 0x8000).to_rom_offset(), None);
+        assert_eq!(SnesAddr::new(0x8f, 0x1000).to_rom_offset(), None);
+        assert_eq!(addr.to_string(), "$8f:91f8");
+        assert_eq!(SnesAddr::new(0x8f, 0xfffe).wrapping_add(3).offset(), 0x0001);
+    }
+
+    #[test]
+    fn follows_flags_and_branches() {
+        let mut rom = vec![0xff; 0x80000];
+        let code = [
+            0xc2, 0x30, // REP #$30
+            0xa9, 0x34, 0x12, // LDA #$1234
+            0xe2, 0x20, // SEP #$20
+            0xa9, 0x05, // LDA #$05
+            0xf0, 0x04, // BEQ $800f
+            0x22, 0x00, 0x80, 0x80, // JSL $808000
+            0x60, // RTS
+        ];
+        let start = SnesAddr::new(0x8f, 0x8000);
+        let offset = start.to_rom_offset().unwrap();
+        rom[offset..offset + code.len()].copy_from_slice(&code);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x80_8000, "Common");
+        let listing = disassemble(&rom, start, Flags::default(), Some(&symbols)).unwrap();
+        assert_eq!(
+            listing.to_string(),
+            "loc_8f8000:\n\
+             \x20 $8f:8000  c2 30        REP #$30\n\
+             \x20 $8f:8002  a9 34 12     LDA #$1234\n\
+             \x20 $8f:8005  e2 20        SEP #$20\n\
+             \x20 $8f:8007  a9 05        LDA #$05\n\
+             \x20 $8f:8009  f0 04        BEQ loc_8f800f\n\
+             \x20 $8f:800b  22 00 80 80  JSL Common\n\
+             loc_8f800f:\n\
+             \x20 $8f:800f  60           RTS\n"
+        );
+    }
+}
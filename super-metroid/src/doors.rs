@@ -0,0 +1,371 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{BlockType, DoorData, RoomMdb, SuperMetroidData};
+
+// Screens are 16x16 blocks.
+const SCREEN_BLOCKS: usize = 16;
+
+// [door index] -> screens holding that door's blocks.
+type DoorScreens = HashMap<usize, HashSet<(u8, u8)>>;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct DoorRef {
+    pub room_ptr: u16, // bank $8f
+    pub door_index: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum DoorConnection {
+    // The destination room has a door leading back through this one.
+    Paired { reverse: DoorRef },
+    // The destination room has no door leading back to the source room.
+    OneWay,
+    // The destination room has doors leading back to the source room but
+    // none of them could be matched to where this door places Samus.
+    Unpaired { candidates: Vec<DoorRef> },
+    // The door list entry has no destination room.
+    NoDestination,
+    // The destination room was not loaded so its doors are unknown.
+    Unresolved,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoorLink {
+    pub door: DoorRef,
+    pub dest_room_ptr: u16,
+    pub elevator: bool,
+    pub area_change: bool,
+    pub connection: DoorConnection,
+    // States of the destination room that are only used when entering
+    // through this door.
+    pub triggers_states: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoorMap {
+    pub links: BTreeMap<DoorRef, DoorLink>,
+}
+
+// Returns the block coordinates of each door index's door blocks in a room.
+//
+// Door blocks store their index into the room's door list in their bts.  All
+// states are considered since they do not always share level data.
+pub fn door_blocks(sm: &SuperMetroidData, mdb: &RoomMdb) -> HashMap<usize, Vec<(usize, usize)>> {
+    let room_blocks_w = mdb.width as usize * SCREEN_BLOCKS;
+    let mut blocks: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    if room_blocks_w == 0 {
+        return blocks;
+    }
+
+    for state in &mdb.states {
+        let data = match sm.level_data.get(&state.data.level_data) {
+            Some(data) => data,
+            None => continue,
+        };
+        for (i, block) in data.layer_1.iter().enumerate() {
+            if block.ty != BlockType::DoorBlock {
+                continue;
+            }
+            blocks
+                .entry(data.bts[i] as usize)
+                .or_default()
+                .push((i % room_blocks_w, i / room_blocks_w));
+        }
+    }
+
+    for coords in blocks.values_mut() {
+        coords.sort_unstable();
+        coords.dedup();
+    }
+    blocks
+}
+
+// Returns the screens that contain door blocks for each door index of a room.
+fn door_screens(sm: &SuperMetroidData, mdb: &RoomMdb) -> DoorScreens {
+    door_blocks(sm, mdb)
+        .into_iter()
+        .map(|(index, coords)| {
+            let screens = coords
+                .iter()
+                .map(|(x, y)| ((x / SCREEN_BLOCKS) as u8, (y / SCREEN_BLOCKS) as u8))
+                .collect();
+            (index, screens)
+        })
+        .collect()
+}
+
+impl DoorMap {
+    pub fn new(sm: &SuperMetroidData) -> DoorMap {
+        let screens: HashMap<u16, DoorScreens> = sm
+            .room_mdb
+            .iter()
+            .map(|(room_ptr, mdb)| (*room_ptr, door_screens(sm, mdb)))
+            .collect();
+
+        let mut links = BTreeMap::new();
+        for (room_ptr, mdb) in &sm.room_mdb {
+            for (door_index, door) in mdb.door_list.iter().enumerate() {
+                let door_ref = DoorRef {
+                    room_ptr: *room_ptr,
+                    door_index,
+                };
+                links.insert(
+                    door_ref,
+                    DoorLink {
+                        door: door_ref,
+                        dest_room_ptr: door.dest_room_ptr,
+                        elevator: door.is_elevator(),
+                        area_change: door.is_area_change(),
+                        connection: Self::resolve(sm, &screens, *room_ptr, door),
+                        triggers_states: sm
+                            .room_mdb
+                            .get(&door.dest_room_ptr)
+                            .map(|dest| dest.states_entered_by(door.door_ptr))
+                            .unwrap_or_default(),
+                    },
+                );
+            }
+        }
+
+        // Doors that could not be told apart on their own may still be
+        // resolved by a candidate that was matched back to them.
+        let mut resolved = Vec::new();
+        for (door_ref, link) in &links {
+            if let DoorConnection::Unpaired { candidates } = &link.connection {
+                let back: Vec<&DoorRef> = candidates
+                    .iter()
+                    .filter(|c| match links[c].connection {
+                        DoorConnection::Paired { reverse } => reverse == *door_ref,
+                        _ => false,
+                    })
+                    .collect();
+                if back.len() == 1 {
+                    resolved.push((*door_ref, *back[0]));
+                }
+            }
+        }
+        for (door_ref, reverse) in resolved {
+            links.get_mut(&door_ref).unwrap().connection = DoorConnection::Paired { reverse };
+        }
+
+        DoorMap { links }
+    }
+
+    fn resolve(
+        sm: &SuperMetroidData,
+        screens: &HashMap<u16, DoorScreens>,
+        room_ptr: u16,
+        door: &DoorData,
+    ) -> DoorConnection {
+        if door.dest_room_ptr == 0 {
+            return DoorConnection::NoDestination;
+        }
+        let dest = match sm.room_mdb.get(&door.dest_room_ptr) {
+            Some(dest) => dest,
+            None => return DoorConnection::Unresolved,
+        };
+
+        let candidates: Vec<DoorRef> = dest
+            .door_list
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.dest_room_ptr == room_ptr)
+            .map(|(i, _)| DoorRef {
+                room_ptr: door.dest_room_ptr,
+                door_index: i,
+            })
+            .collect();
+
+        // The door's screen coordinates are where Samus comes out in the
+        // destination room, which is the screen holding the reverse door.
+        let screen = (door.screen_x(), door.screen_y());
+        let matched: Vec<DoorRef> = candidates
+            .iter()
+            .filter(|c| {
+                screens
+                    .get(&c.room_ptr)
+                    .and_then(|s| s.get(&c.door_index))
+                    .is_some_and(|s| s.contains(&screen))
+            })
+            .cloned()
+            .collect();
+
+        match (candidates.len(), matched.len()) {
+            (0, _) => DoorConnection::OneWay,
+            (_, 1) => DoorConnection::Paired {
+                reverse: matched[0],
+            },
+            // Elevators are not placed with door blocks so there is nothing
+            // to match against.  A single candidate is unambiguous though.
+            (1, 0) if door.is_elevator() => DoorConnection::Paired {
+                reverse: candidates[0],
+            },
+            // Samus comes out away from the only door back, so this door
+            // can't be returned through.
+            (1, 0) => DoorConnection::OneWay,
+            _ => DoorConnection::Unpaired { candidates },
+        }
+    }
+
+    pub fn get(&self, door: &DoorRef) -> Option<&DoorLink> {
+        self.links.get(door)
+    }
+
+    pub fn reverse(&self, door: &DoorRef) -> Option<DoorRef> {
+        match self.links.get(door)?.connection {
+            DoorConnection::Paired { reverse } => Some(reverse),
+            _ => None,
+        }
+    }
+
+    pub fn one_way_doors(&self) -> impl Iterator<Item = &DoorLink> {
+        self.links
+            .values()
+            .filter(|l| l.connection == DoorConnection::OneWay)
+    }
+
+    pub fn unpaired_doors(&self) -> impl Iterator<Item = &DoorLink> {
+        self.links
+            .values()
+            .filter(|l| matches!(l.connection, DoorConnection::Unpaired { .. }))
+    }
+
+    pub fn unresolved_doors(&self) -> impl Iterator<Item = &DoorLink> {
+        self.links
+            .values()
+            .filter(|l| l.connection == DoorConnection::Unresolved)
+    }
+
+    pub fn elevators(&self) -> impl Iterator<Item = &DoorLink> {
+        self.links.values().filter(|l| l.elevator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{add_room, door};
+    use super::super::*;
+    use super::*;
+
+    #[test]
+    fn doors_resolve_to_reverse_door() {
+        let mut sm = SuperMetroidData::default();
+        // Room a has two doors into room b which are told apart by the
+        // screen they place Samus in.  Room b has a door to nowhere and a
+        // one way door into room c.
+        add_room(
+            &mut sm,
+            0x91f8,
+            1,
+            &[(0, 0), (1, 0)],
+            vec![door(0x92fd, 0, 0), door(0x92fd, 2, 0)],
+        );
+        add_room(
+            &mut sm,
+            0x92fd,
+            3,
+            &[(0, 0), (1, 2), (2, 1), (3, 1)],
+            vec![
+                door(0x91f8, 0, 0),
+                door(0x91f8, 0, 0),
+                door(0x0000, 0, 0),
+                door(0x93aa, 0, 0),
+            ],
+        );
+        add_room(&mut sm, 0x93aa, 1, &[], vec![]);
+        // Entering room c through b's door picks c's first state.
+        sm.room_mdb.get_mut(&0x92fd).unwrap().door_list[3].door_ptr = 0x8916;
+        let c = sm.room_mdb.get_mut(&0x93aa).unwrap();
+        let mut state = State {
+            condition: StateCondition::DoorPointerIs { value: 0x8916 },
+            data: c.states[0].data.clone(),
+        };
+        state.data.level_data += 1;
+        c.states.insert(0, state);
+
+        let map = DoorMap::new(&sm);
+        let a0 = DoorRef {
+            room_ptr: 0x91f8,
+            door_index: 0,
+        };
+        let a1 = DoorRef {
+            room_ptr: 0x91f8,
+            door_index: 1,
+        };
+        let b0 = DoorRef {
+            room_ptr: 0x92fd,
+            door_index: 0,
+        };
+        let b1 = DoorRef {
+            room_ptr: 0x92fd,
+            door_index: 1,
+        };
+        assert_eq!(map.reverse(&a0), Some(b0));
+        assert_eq!(map.reverse(&a1), Some(b1));
+        // b0 and b1 both lead to the only screen of room a but can be
+        // paired through the doors that lead to them.
+        assert_eq!(map.reverse(&b0), Some(a0));
+        assert_eq!(map.reverse(&b1), Some(a1));
+        assert_eq!(map.unpaired_doors().count(), 0);
+        assert_eq!(
+            map.links[&DoorRef {
+                room_ptr: 0x92fd,
+                door_index: 2
+            }]
+                .connection,
+            DoorConnection::NoDestination
+        );
+        assert_eq!(map.links[&a0].triggers_states, Vec::<usize>::new());
+        assert_eq!(
+            map.links[&DoorRef {
+                room_ptr: 0x92fd,
+                door_index: 3
+            }]
+                .triggers_states,
+            vec![0]
+        );
+        assert_eq!(
+            map.one_way_doors()
+                .map(|l| l.door.door_index)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn unmatched_single_candidate_is_one_way_unless_elevator() {
+        let mut sm = SuperMetroidData::default();
+        // Room a's doors into b place Samus on screen 1, away from b's only
+        // door back.  The elevator pairs anyway; the plain door doesn't.
+        let mut elevator = door(0x92fd, 1, 0);
+        elevator.elevator_props = DOOR_FLAG_ELEVATOR;
+        add_room(
+            &mut sm,
+            0x91f8,
+            1,
+            &[(0, 0)],
+            vec![door(0x92fd, 1, 0), elevator, door(0x9999, 0, 0)],
+        );
+        add_room(&mut sm, 0x92fd, 2, &[(0, 0)], vec![door(0x91f8, 0, 0)]);
+
+        let map = DoorMap::new(&sm);
+        let a = |door_index| DoorRef {
+            room_ptr: 0x91f8,
+            door_index,
+        };
+        assert_eq!(map.links[&a(0)].connection, DoorConnection::OneWay);
+        assert_eq!(
+            map.links[&a(1)].connection,
+            DoorConnection::Paired {
+                reverse: DoorRef {
+                    room_ptr: 0x92fd,
+                    door_index: 0
+                }
+            }
+        );
+        // Room 0x9999 was never loaded.
+        assert_eq!(map.links[&a(2)].connection, DoorConnection::Unresolved);
+        assert_eq!(map.unresolved_doors().count(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use super::symbols::SymbolTable;
+use super::{Enemy, EnemyData};
+
+// Length of the debug names in bank $B4.
+pub const ENEMY_NAME_LEN: usize = 10;
+
+// Names the community uses for enemy species, keyed by the species' address
+// in bank $A0.  Only used for enemies whose header has no name pointer.
+const COMMUNITY_NAMES: &[(u16, &str)] = &[
+    (0xdd7f, "Metroid"),
+    (0xddbf, "Crocomire"),
+    (0xde3f, "Draygon"),
+    (0xdf3f, "Spore Spawn"),
+    (0xe0ff, "Ceres Ridley"),
+    (0xe13f, "Ridley"),
+    (0xe2bf, "Kraid"),
+    (0xe4bf, "Phantoon"),
+    (0xec3f, "Mother Brain"),
+];
+
+// Decodes a debug name.  The names are upper case ASCII padded with spaces.
+// Anything after a 0x00 or 0xff byte is padding and any other byte outside
+// of printable ASCII is shown as '?'.
+pub fn decode_name(data: &[u8]) -> String {
+    data.iter()
+        .take(ENEMY_NAME_LEN)
+        .take_while(|b| **b != 0x00 && **b != 0xff)
+        .map(|b| match b {
+            0x20..=0x7e => *b as char,
+            _ => '?',
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+pub fn community_name(addr: u16) -> Option<&'static str> {
+    COMMUNITY_NAMES
+        .iter()
+        .find(|(a, _)| *a == addr)
+        .map(|(_, name)| *name)
+}
+
+impl EnemyData {
+    // The routine pointers in the header.  All of them are in `bank`.
+    pub fn ai_pointers(&self) -> [(&'static str, u16); 9] {
+        [
+            ("init_ai", self.init_ai),
+            ("main_ai", self.main_ai),
+            ("grapple_ai", self.grapple_ai),
+            ("hurt_ai", self.hurt_ai),
+            ("frozen_ai", self.frozen_ai),
+            ("x_ray_ai", self.x_ray_ai),
+            ("power_bomb_reaction", self.power_bomb_reaction),
+            ("enemy_touch", self.enemy_touch),
+            ("enemy_shot", self.enemy_shot),
+        ]
+    }
+}
+
+impl Enemy {
+    // Returns the debug name, falling back to the community name and then
+    // the species address.
+    pub fn display_name(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
+        }
+        match community_name(self.addr) {
+            Some(name) => name.to_string(),
+            None => format!("enemy_{:04x}", self.addr),
+        }
+    }
+
+    // Fills `ai_symbols` with the names of the header's routine pointers.
+    // Pointers outside of ROM and ones with no symbol are left out.
+    pub fn resolve_symbols(&mut self, symbols: &SymbolTable) {
+        let bank = self.data.bank as u32;
+        self.ai_symbols = self
+            .data
+            .ai_pointers()
+            .iter()
+            .filter(|(_, ptr)| *ptr >= 0x8000)
+            .filter_map(|(field, ptr)| {
+                let name = symbols.resolve(bank << 16 | *ptr as u32)?;
+                Some((*field, name))
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_trimmed_and_tolerant() {
+        assert_eq!(decode_name(b"ZOA       "), "ZOA");
+        assert_eq!(decode_name(b"SIDEHOPPERXX"), "SIDEHOPPER");
+        assert_eq!(decode_name(b"GEEGA\x00\xc3\x28  "), "GEEGA");
+        assert_eq!(decode_name(b" RIP\x80ER   "), "RIP?ER");
+        assert_eq!(decode_name(b"\xff\xff\xff"), "");
+    }
+}
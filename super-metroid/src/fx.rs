@@ -0,0 +1,583 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::buffer::RgbaBuffer;
+use super::graphics::de_planar_tiles;
+use super::{rommap, Color, Tiles};
+
+// Each FX entry is 16 bytes in bank $83.
+const FX_ENTRY_SIZE: usize = 16;
+// Guards against walking garbage when a list is not terminated.
+const MAX_FX_ENTRIES: usize = 16;
+const MAX_ANIMATED_TILES_FRAMES: usize = 256;
+
+// Animated tiles object instructions (bank $87).
+const ANIMATED_TILES_DELETE: u16 = 0x80b2;
+const ANIMATED_TILES_GOTO: u16 = 0x80b7;
+
+// Palette FX object instructions (bank $8d).
+const PALETTE_FX_DONE: u16 = 0xc595;
+const PALETTE_FX_DELETE: u16 = 0xc5cf;
+const PALETTE_FX_GOTO: u16 = 0xc61e;
+const PALETTE_FX_SET_COLOR_INDEX: u16 = 0xc655;
+const MAX_PALETTE_FX_COLORS: usize = 256;
+const MAX_PALETTE_FX_FRAMES: usize = 256;
+
+// Number of objects selectable by an FX entry's animated tiles and palette
+// FX bitsets.
+pub const ANIMATED_TILES_BITS: usize = 8;
+pub const PALETTE_FX_BITS: usize = 8;
+
+// The game runs at 60 frames a second.  Animation timers count frames.
+pub const TICKS_PER_SECOND: u32 = 60;
+
+// Frame timing shared by the objects driven by instruction lists of
+// (timer, data) pairs.
+pub trait Timeline {
+    // Frames each entry is shown for.
+    fn durations(&self) -> Vec<u16>;
+    // Entry the instruction list jumps back to after the last one.  Lists
+    // without a loop keep showing their last entry.
+    fn loop_start(&self) -> Option<usize>;
+
+    // Returns the entry shown `tick` frames after the object is spawned.
+    fn frame_index_at(&self, tick: u32) -> Option<usize> {
+        // A timer of 0 still shows the entry for one tick.
+        let durations: Vec<u32> = self
+            .durations()
+            .iter()
+            .map(|d| (*d).max(1) as u32)
+            .collect();
+        let total: u32 = durations.iter().sum();
+        let mut t = tick;
+        if t >= total {
+            match self.loop_start() {
+                Some(start) => {
+                    let intro: u32 = durations[..start].iter().sum();
+                    t = intro + (t - intro) % (total - intro);
+                }
+                None => return durations.len().checked_sub(1),
+            }
+        }
+
+        for (i, duration) in durations.iter().enumerate() {
+            if t < *duration {
+                return Some(i);
+            }
+            t -= duration;
+        }
+        None
+    }
+
+    // Returns the ticks before `max_ticks` at which the shown entry changes.
+    fn frame_changes(&self, max_ticks: u32) -> Vec<u32> {
+        let durations = self.durations();
+        let mut ticks = Vec::new();
+        if durations.is_empty() {
+            return ticks;
+        }
+        let mut i = 0;
+        let mut tick = 0;
+        while tick < max_ticks {
+            ticks.push(tick);
+            tick += durations[i].max(1) as u32;
+            i += 1;
+            if i == durations.len() {
+                match self.loop_start() {
+                    Some(start) => i = start,
+                    None => break,
+                }
+            }
+        }
+        ticks
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FxEntry {
+    // Door the entry applies to.  0 is the default entry.
+    pub door_ptr: u16,
+    pub base_y: u16,
+    pub target_y: u16,
+    pub y_velocity: u16,
+    pub timer: u8,
+    pub fx_type: u8,
+    pub default_layer_blending: u8,
+    pub layer_3_blending: u8,
+    pub liquid_options: u8,
+    pub palette_fx: u8,
+    pub animated_tiles: u8,
+    pub palette_blend: u8,
+}
+
+impl FxEntry {
+    fn load(data: &[u8]) -> Result<FxEntry, Error> {
+        let mut r = Cursor::new(data);
+        Ok(FxEntry {
+            door_ptr: r.read_u16::<LittleEndian>()?,
+            base_y: r.read_u16::<LittleEndian>()?,
+            target_y: r.read_u16::<LittleEndian>()?,
+            y_velocity: r.read_u16::<LittleEndian>()?,
+            timer: r.read_u8()?,
+            fx_type: r.read_u8()?,
+            default_layer_blending: r.read_u8()?,
+            layer_3_blending: r.read_u8()?,
+            liquid_options: r.read_u8()?,
+            palette_fx: r.read_u8()?,
+            animated_tiles: r.read_u8()?,
+            palette_blend: r.read_u8()?,
+        })
+    }
+}
+
+// Loads the FX list at `fx_ptr` (bank $83).  The game takes the first entry
+// whose door matches the door Samus came through.  A door of 0 matches any
+// door and ends the list, as does a door of 0xffff which matches none.
+pub fn load_fx_list(rom_data: &[u8], fx_ptr: u16) -> Result<Vec<FxEntry>, Error> {
+    let mut entries = Vec::new();
+    if fx_ptr < 0x8000 {
+        return Ok(entries);
+    }
+
+    let mut data = bank_data(rom_data, 0x83, fx_ptr)?;
+    for _ in 0..MAX_FX_ENTRIES {
+        let door_ptr = Cursor::new(data).read_u16::<LittleEndian>()?;
+        if door_ptr == 0xffff {
+            return Ok(entries);
+        }
+        entries.push(FxEntry::load(data)?);
+        if door_ptr == 0x0000 {
+            return Ok(entries);
+        }
+        data = data.get(FX_ENTRY_SIZE..).unwrap_or(&[]);
+    }
+    Err(format_err!("fx list {:04x} is not terminated", fx_ptr))
+}
+
+// Returns the ROM from `ptr` in `bank` on.
+fn bank_data(rom_data: &[u8], bank: usize, ptr: u16) -> Result<&[u8], Error> {
+    if ptr < 0x8000 {
+        return Err(format_err!("bad pointer {:02x}:{:04x}", bank, ptr));
+    }
+    rom_data.get(crate::rom_addr!(bank, ptr)..).ok_or_else(|| {
+        format_err!(
+            "pointer {:02x}:{:04x} is past the end of the ROM",
+            bank,
+            ptr
+        )
+    })
+}
+
+// Returns the instruction list pointer `len` bytes after `list_ptr`.
+fn next_instruction(list_ptr: u16, len: u16) -> Result<u16, Error> {
+    list_ptr
+        .checked_add(len)
+        .ok_or_else(|| format_err!("instruction list {:04x} runs past its bank", list_ptr))
+}
+
+// Returns the FX entry used when entering through `entry_door_ptr`.
+pub fn select_fx(entries: &[FxEntry], entry_door_ptr: Option<u16>) -> Option<&FxEntry> {
+    entries
+        .iter()
+        .find(|fx| fx.door_ptr == 0 || Some(fx.door_ptr) == entry_door_ptr)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnimatedTilesFrame {
+    // Frames the tiles are shown for.
+    pub duration: u16,
+    pub tiles: Tiles,
+}
+
+// An animated tiles object (bank $87) copies a new set of tiles into VRAM
+// each time its frame timer runs out.
+#[derive(Debug, Serialize)]
+pub struct AnimatedTiles {
+    // VRAM word address the tiles are copied to.
+    pub vram_addr: u16,
+    pub frames: Vec<AnimatedTilesFrame>,
+    // Frame the instruction list jumps back to after the last frame.  Objects
+    // that delete themselves keep showing their last frame.
+    pub loop_start: Option<usize>,
+}
+
+impl AnimatedTiles {
+    // Loads the object whose header is at `ptr` in bank $87.  The header is
+    // the instruction list pointer, the size of each frame's tile data and
+    // the VRAM address.
+    pub fn load(rom_data: &[u8], ptr: u16) -> Result<AnimatedTiles, Error> {
+        let mut r = Cursor::new(bank_data(rom_data, 0x87, ptr)?);
+        let mut list_ptr = r.read_u16::<LittleEndian>()?;
+        let size = r.read_u16::<LittleEndian>()? as usize;
+        let vram_addr = r.read_u16::<LittleEndian>()?;
+
+        // Instruction list pointer of each frame so gotos can be resolved.
+        let mut frame_ptrs = Vec::new();
+        let mut frames = Vec::new();
+        let loop_target = loop {
+            if frames.len() >= MAX_ANIMATED_TILES_FRAMES {
+                return Err(format_err!(
+                    "animated tiles {:04x} has too many frames",
+                    ptr
+                ));
+            }
+            let mut r = Cursor::new(bank_data(rom_data, 0x87, list_ptr)?);
+            let word = r.read_u16::<LittleEndian>()?;
+            match word {
+                ANIMATED_TILES_DELETE => break None,
+                ANIMATED_TILES_GOTO => break Some(r.read_u16::<LittleEndian>()?),
+                0x8000..=0xffff => {
+                    return Err(format_err!(
+                        "unknown animated tiles instruction {:04x} at {:04x}",
+                        word,
+                        list_ptr
+                    ))
+                }
+                duration => {
+                    let src = bank_data(rom_data, 0x87, r.read_u16::<LittleEndian>()?)?;
+                    let mut data = src
+                        .get(..size)
+                        .ok_or_else(|| format_err!("animated tiles data out of range"))?
+                        .to_vec();
+                    de_planar_tiles(&mut data);
+                    frame_ptrs.push(list_ptr);
+                    frames.push(AnimatedTilesFrame {
+                        duration,
+                        tiles: Tiles { data },
+                    });
+                    list_ptr = next_instruction(list_ptr, 4)?;
+                }
+            }
+        };
+
+        let loop_start =
+            match loop_target {
+                Some(target) => Some(frame_ptrs.iter().position(|p| *p == target).ok_or_else(
+                    || format_err!("animated tiles goto {:04x} not a frame", target),
+                )?),
+                None => None,
+            };
+
+        Ok(AnimatedTiles {
+            vram_addr,
+            frames,
+            loop_start,
+        })
+    }
+}
+
+impl Timeline for AnimatedTiles {
+    fn durations(&self) -> Vec<u16> {
+        self.frames.iter().map(|f| f.duration).collect()
+    }
+
+    fn loop_start(&self) -> Option<usize> {
+        self.loop_start
+    }
+}
+
+impl AnimatedTiles {
+    // Returns the frame shown `tick` frames after the object is spawned.
+    pub fn frame_at(&self, tick: u32) -> Option<&AnimatedTilesFrame> {
+        self.frames.get(self.frame_index_at(tick)?)
+    }
+}
+
+// Loads the table of animated tiles objects selected by the bits of
+// `FxEntry::animated_tiles`.
+pub fn load_animated_tiles_table(rom_data: &[u8]) -> Result<Vec<u16>, Error> {
+    let mut r = Cursor::new(&rom_data[rommap::FX_ANIMATED_TILES_TABLE..]);
+    let mut ptrs = Vec::with_capacity(ANIMATED_TILES_BITS);
+    for _ in 0..ANIMATED_TILES_BITS {
+        ptrs.push(r.read_u16::<LittleEndian>()?);
+    }
+    Ok(ptrs)
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaletteFxFrame {
+    // Frames the colors are shown for.
+    pub duration: u16,
+    // (CGRAM color index, color) pairs written by this frame.
+    pub colors: Vec<(usize, Color)>,
+}
+
+// A palette FX object (bank $8d) rewrites a run of palette colors each time
+// its frame timer runs out.  Heated Norfair's glow and Tourian's lights are
+// done this way.
+#[derive(Debug, Serialize)]
+pub struct PaletteFx {
+    pub frames: Vec<PaletteFxFrame>,
+    pub loop_start: Option<usize>,
+}
+
+impl PaletteFx {
+    // Loads the object whose header is at `ptr` in bank $8d.  The header is
+    // an initialization routine pointer followed by the instruction list
+    // pointer.
+    pub fn load(rom_data: &[u8], ptr: u16) -> Result<PaletteFx, Error> {
+        let mut r = Cursor::new(bank_data(rom_data, 0x8d, ptr)?);
+        let _init_ptr = r.read_u16::<LittleEndian>()?;
+        let mut list_ptr = r.read_u16::<LittleEndian>()?;
+
+        let mut color_index = 0;
+        let mut frame_ptrs = Vec::new();
+        let mut frames = Vec::new();
+        let loop_target = loop {
+            if frames.len() >= MAX_PALETTE_FX_FRAMES {
+                return Err(format_err!("palette fx {:04x} has too many frames", ptr));
+            }
+            let mut r = Cursor::new(bank_data(rom_data, 0x8d, list_ptr)?);
+            let word = r.read_u16::<LittleEndian>()?;
+            match word {
+                PALETTE_FX_DELETE => break None,
+                PALETTE_FX_GOTO => break Some(r.read_u16::<LittleEndian>()?),
+                PALETTE_FX_SET_COLOR_INDEX => {
+                    // The index is a byte offset into CGRAM.
+                    color_index = r.read_u16::<LittleEndian>()? as usize / 2;
+                    list_ptr = next_instruction(list_ptr, 4)?;
+                }
+                0x8000..=0xffff => {
+                    return Err(format_err!(
+                        "unknown palette fx instruction {:04x} at {:04x}",
+                        word,
+                        list_ptr
+                    ))
+                }
+                duration => {
+                    let mut colors = Vec::new();
+                    loop {
+                        let val = r.read_u16::<LittleEndian>()?;
+                        if val == PALETTE_FX_DONE {
+                            break;
+                        }
+                        if colors.len() >= MAX_PALETTE_FX_COLORS {
+                            return Err(format_err!("palette fx {:04x} frame not terminated", ptr));
+                        }
+                        colors.push((color_index + colors.len(), Color::from_bgr555(val)));
+                    }
+                    frame_ptrs.push(list_ptr);
+                    list_ptr = next_instruction(list_ptr, 4 + colors.len() as u16 * 2)?;
+                    frames.push(PaletteFxFrame { duration, colors });
+                }
+            }
+        };
+
+        let loop_start = match loop_target {
+            Some(target) => Some(
+                frame_ptrs
+                    .iter()
+                    .position(|p| *p == target)
+                    .ok_or_else(|| format_err!("palette fx goto {:04x} not a frame", target))?,
+            ),
+            None => None,
+        };
+
+        Ok(PaletteFx { frames, loop_start })
+    }
+
+    // Returns the frame shown `tick` frames after the object is spawned.
+    pub fn frame_at(&self, tick: u32) -> Option<&PaletteFxFrame> {
+        self.frames.get(self.frame_index_at(tick)?)
+    }
+
+    // Returns the frame to use for a still image.  This is the longest shown
+    // frame of the loop, which for glows is the steady lit state rather than
+    // the first, unlit, one.  Objects that do not loop end on their last
+    // frame.
+    pub fn representative_frame(&self) -> Option<&PaletteFxFrame> {
+        let start = match self.loop_start {
+            Some(start) => start,
+            None => return self.frames.last(),
+        };
+        self.frames[start..]
+            .iter()
+            .rev()
+            .max_by_key(|frame| frame.duration)
+    }
+}
+
+impl Timeline for PaletteFx {
+    fn durations(&self) -> Vec<u16> {
+        self.frames.iter().map(|f| f.duration).collect()
+    }
+
+    fn loop_start(&self) -> Option<usize> {
+        self.loop_start
+    }
+}
+
+// Loads the per tile set tables of palette FX objects selected by the bits
+// of `FxEntry::palette_fx`.
+pub fn load_palette_fx_table(rom_data: &[u8]) -> Result<Vec<Vec<u16>>, Error> {
+    let mut r = Cursor::new(&rom_data[rommap::PALETTE_FX_TABLE..]);
+    let mut tables = Vec::with_capacity(rommap::TILESET_POINTER_TABLE_COUNT);
+    for _ in 0..rommap::TILESET_POINTER_TABLE_COUNT {
+        let table_ptr = r.read_u16::<LittleEndian>()?;
+        let mut ptrs = Vec::with_capacity(PALETTE_FX_BITS);
+        if table_ptr >= 0x8000 {
+            let mut table_r = Cursor::new(
+                &rom_data[crate::rom_addr!(rommap::PALETTE_FX_TABLE_BANK, table_ptr)..],
+            );
+            for _ in 0..PALETTE_FX_BITS {
+                ptrs.push(table_r.read_u16::<LittleEndian>()?);
+            }
+        }
+        tables.push(ptrs);
+    }
+    Ok(tables)
+}
+
+// Returns the ticks at which any of `animations` changes frame, starting at
+// 0 and stopping before `max_ticks`.
+pub fn animation_ticks(animations: &[&dyn Timeline], max_ticks: u32) -> Vec<u32> {
+    let mut ticks: Vec<u32> = animations
+        .iter()
+        .flat_map(|a| a.frame_changes(max_ticks))
+        .collect();
+    ticks.push(0);
+    ticks.sort_unstable();
+    ticks.dedup();
+    ticks
+}
+
+pub struct RoomFrame {
+    pub image: RgbaBuffer,
+    // Game frames this image is shown for.
+    pub ticks: u32,
+}
+
+// Writes rendered frames as an animated GIF.
+#[cfg(feature = "render")]
+pub fn write_gif<W: std::io::Write>(w: W, frames: Vec<RoomFrame>) -> Result<(), Error> {
+    let mut encoder = image::gif::Encoder::new(w);
+    for frame in frames {
+        let width = frame.image.width as u16;
+        let height = frame.image.height as u16;
+        let mut pixels = frame.image.pixels;
+        let mut gif_frame = image::gif::Frame::from_rgba(width, height, &mut pixels);
+        // GIF delays are in hundredths of a second.
+        gif_frame.delay = ((frame.ticks * 100 + TICKS_PER_SECOND / 2) / TICKS_PER_SECOND) as u16;
+        encoder.encode(&gif_frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BANK_87: usize = crate::rom_addr!(0x87, 0x8000);
+
+    fn put_u16s(rom: &mut [u8], offset: usize, words: &[u16]) {
+        for (i, w) in words.iter().enumerate() {
+            rom[offset + i * 2..offset + i * 2 + 2].copy_from_slice(&w.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn fx_list_selects_door_entry() {
+        let mut rom = vec![0u8; BANK_87];
+        let list = crate::rom_addr!(0x83, 0x8100);
+        put_u16s(&mut rom, list, &[0x8916]);
+        rom[list + 0x9] = 0x02;
+        put_u16s(&mut rom, list + FX_ENTRY_SIZE, &[0x0000]);
+        rom[list + FX_ENTRY_SIZE + 0xe] = 0x03;
+
+        let entries = load_fx_list(&rom, 0x8100).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(select_fx(&entries, Some(0x8916)).unwrap().fx_type, 0x02);
+        assert_eq!(select_fx(&entries, None).unwrap().animated_tiles, 0x03);
+
+        put_u16s(&mut rom, list, &[0xffff]);
+        assert!(load_fx_list(&rom, 0x8100).unwrap().is_empty());
+        assert!(load_fx_list(&rom, 0x0000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn animated_tiles_loop() {
+        let mut rom = vec![0u8; BANK_87 + 0x8000];
+        // Header, then an intro frame followed by two looping frames.
+        put_u16s(&mut rom, BANK_87, &[0x8010, 0x0020, 0x3000]);
+        put_u16s(
+            &mut rom,
+            BANK_87 + 0x10,
+            &[
+                5,
+                0x9000,
+                10,
+                0x9020,
+                20,
+                0x9040,
+                ANIMATED_TILES_GOTO,
+                0x8014,
+            ],
+        );
+        rom[BANK_87 + 0x1040] = 0xff;
+
+        let anim = AnimatedTiles::load(&rom, 0x8000).unwrap();
+        assert_eq!(anim.vram_addr, 0x3000);
+        assert_eq!(anim.frames.len(), 3);
+        assert_eq!(anim.loop_start, Some(1));
+        assert_eq!(anim.frames[2].tiles.data.len(), 0x20);
+        assert_ne!(anim.frames[2].tiles.data, vec![0; 0x20]);
+
+        let duration_at = |tick| anim.frame_at(tick).unwrap().duration;
+        assert_eq!(duration_at(4), 5);
+        assert_eq!(duration_at(5), 10);
+        assert_eq!(duration_at(34), 20);
+        assert_eq!(duration_at(35), 10);
+        assert_eq!(anim.frame_changes(50), vec![0, 5, 15, 35, 45]);
+
+        // A list running off the end of the bank is an error, not a panic.
+        put_u16s(&mut rom, BANK_87 + 0x7ff0, &[0xfffc, 0x0020, 0x3000]);
+        put_u16s(&mut rom, BANK_87 + 0x7ffc, &[1, 0x9000]);
+        assert!(AnimatedTiles::load(&rom, 0xfff0).is_err());
+    }
+
+    #[test]
+    fn palette_fx_frames() {
+        let bank_8d = crate::rom_addr!(0x8d, 0x8000);
+        let mut rom = vec![0u8; bank_8d + 0x8000];
+        put_u16s(&mut rom, bank_8d, &[0x0000, 0x8010]);
+        put_u16s(
+            &mut rom,
+            bank_8d + 0x10,
+            &[
+                PALETTE_FX_SET_COLOR_INDEX,
+                0x0068,
+                // An unlit frame before the loop, then a long lit frame and
+                // a short flicker.
+                4,
+                0x0000,
+                PALETTE_FX_DONE,
+                30,
+                0x001f,
+                0x03e0,
+                PALETTE_FX_DONE,
+                2,
+                0x7c00,
+                PALETTE_FX_DONE,
+                PALETTE_FX_GOTO,
+                0x801a,
+            ],
+        );
+
+        let fx = PaletteFx::load(&rom, 0x8000).unwrap();
+        assert_eq!(fx.frames.len(), 3);
+        assert_eq!(fx.loop_start, Some(1));
+        let colors: Vec<(usize, u8, u8)> = fx.frames[1]
+            .colors
+            .iter()
+            .map(|(i, c)| (*i, c.r, c.g))
+            .collect();
+        assert_eq!(colors, vec![(0x34, 0xf8, 0x00), (0x35, 0x00, 0xf8)]);
+
+        assert_eq!(fx.frame_at(0).unwrap().duration, 4);
+        assert_eq!(fx.frame_at(35).unwrap().duration, 2);
+        assert_eq!(fx.frame_at(36).unwrap().duration, 30);
+        assert_eq!(fx.representative_frame().unwrap().duration, 30);
+    }
+}
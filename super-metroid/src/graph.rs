@@ -0,0 +1,380 @@
+use petgraph::algo::{astar, tarjan_scc};
+use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
+use petgraph::visit::{Bfs, EdgeRef};
+use std::collections::HashMap;
+
+use super::doors::{DoorMap, DoorRef};
+use super::progress::GameProgress;
+use super::{Area, DoorData, RoomMdb, SuperMetroidData};
+
+#[derive(Debug)]
+pub struct DoorEdge<'a> {
+    pub door: DoorRef,
+    pub data: &'a DoorData,
+}
+
+// Directed graph of rooms connected by doors.
+//
+// Nodes are room pointers (bank $8f).  Every door with a destination is an
+// edge from its room to the destination room.  `DoorGraph` has a node per
+// door instead for paths between doors of the same room.
+pub struct RoomGraph<'a> {
+    sm: &'a SuperMetroidData,
+    pub graph: DiGraph<u16, DoorEdge<'a>>,
+    nodes: HashMap<u16, NodeIndex>,
+    edges: HashMap<DoorRef, EdgeIndex>,
+}
+
+impl<'a> RoomGraph<'a> {
+    pub fn new(sm: &'a SuperMetroidData) -> RoomGraph<'a> {
+        Self::build(sm, |_| true)
+    }
+
+    // Builds the graph of rooms in `area`.  Doors leading out of the area
+    // are left out.
+    pub fn for_area(sm: &'a SuperMetroidData, area: Area) -> RoomGraph<'a> {
+        Self::build(sm, |mdb| mdb.area == area)
+    }
+
+    fn build<F: Fn(&RoomMdb) -> bool>(sm: &'a SuperMetroidData, include: F) -> RoomGraph<'a> {
+        let mut room_ptrs: Vec<u16> = sm
+            .room_mdb
+            .iter()
+            .filter(|(_, mdb)| include(mdb))
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        room_ptrs.sort_unstable();
+
+        let mut graph = DiGraph::new();
+        let mut nodes = HashMap::new();
+        for ptr in &room_ptrs {
+            nodes.insert(*ptr, graph.add_node(*ptr));
+        }
+
+        let mut edges = HashMap::new();
+        for ptr in &room_ptrs {
+            let mdb = &sm.room_mdb[ptr];
+            for (door_index, data) in mdb.door_list.iter().enumerate() {
+                let dest = match nodes.get(&data.dest_room_ptr) {
+                    Some(dest) => *dest,
+                    None => continue,
+                };
+                let door = DoorRef {
+                    room_ptr: *ptr,
+                    door_index,
+                };
+                let edge = graph.add_edge(nodes[ptr], dest, DoorEdge { door, data });
+                edges.insert(door, edge);
+            }
+        }
+
+        RoomGraph {
+            sm,
+            graph,
+            nodes,
+            edges,
+        }
+    }
+
+    pub fn node(&self, room_ptr: u16) -> Option<NodeIndex> {
+        self.nodes.get(&room_ptr).cloned()
+    }
+
+    pub fn door(&self, door: &DoorRef) -> Option<&DoorEdge<'a>> {
+        self.edges.get(door).map(|e| &self.graph[*e])
+    }
+
+    // Returns the state the destination room of `door` is loaded in when
+    // entering through it.
+    pub fn entry_state(&self, door: &DoorRef, progress: &GameProgress) -> Option<usize> {
+        let data = self.door(door)?.data;
+        let dest = self.sm.room_mdb.get(&data.dest_room_ptr)?;
+        Some(dest.state_for(progress, Some(data.door_ptr)))
+    }
+
+    // Returns the doors taken on a shortest path from one room to another.
+    pub fn shortest_path(&self, from: u16, to: u16) -> Option<Vec<DoorRef>> {
+        let start = self.node(from)?;
+        let goal = self.node(to)?;
+        let (_, path) = astar(&self.graph, start, |n| n == goal, |_| 1, |_| 0)?;
+
+        Some(
+            path.windows(2)
+                .map(|w| {
+                    // Several doors can connect the same rooms.  Any of them
+                    // is as short as the others.
+                    let edge = self.graph.edges(w[0]).find(|e| e.target() == w[1]).unwrap();
+                    edge.weight().door
+                })
+                .collect(),
+        )
+    }
+
+    // Returns the rooms reachable from `start`, including `start`, in
+    // breadth first order.
+    pub fn reachable_from(&self, start: u16) -> Vec<u16> {
+        let start = match self.node(start) {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+
+        let mut rooms = Vec::new();
+        let mut bfs = Bfs::new(&self.graph, start);
+        while let Some(n) = bfs.next(&self.graph) {
+            rooms.push(self.graph[n]);
+        }
+        rooms
+    }
+
+    // Returns groups of rooms that can all reach each other.  Rooms in a
+    // component of their own can not be returned to once left.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<u16>> {
+        tarjan_scc(&self.graph)
+            .iter()
+            .map(|component| {
+                let mut rooms: Vec<u16> = component.iter().map(|n| self.graph[*n]).collect();
+                rooms.sort_unstable();
+                rooms
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum DoorGraphEdge<'a> {
+    // Going through a door into the destination room.
+    Door(DoorEdge<'a>),
+    // Walking between two doors of a room.
+    Room { room_ptr: u16 },
+}
+
+// Directed graph of doors.
+//
+// Nodes are doors, standing for the spot in their room next to the door.
+// Going through a door is an edge to the door Samus comes out of in the
+// destination room.  When that door isn't known the edge goes to every door
+// of the destination room instead.  Every pair of doors in a room is
+// connected by walking edges in both directions; the graph knows nothing
+// about what blocks the way inside a room, so callers that do can remove
+// them.
+pub struct DoorGraph<'a> {
+    pub graph: DiGraph<DoorRef, DoorGraphEdge<'a>>,
+    nodes: HashMap<DoorRef, NodeIndex>,
+}
+
+impl<'a> DoorGraph<'a> {
+    pub fn new(sm: &'a SuperMetroidData) -> DoorGraph<'a> {
+        Self::build(sm, |_| true)
+    }
+
+    // Builds the graph of doors in `area`.  Doors leading out of the area
+    // have no edges through them.
+    pub fn for_area(sm: &'a SuperMetroidData, area: Area) -> DoorGraph<'a> {
+        Self::build(sm, |mdb| mdb.area == area)
+    }
+
+    fn build<F: Fn(&RoomMdb) -> bool>(sm: &'a SuperMetroidData, include: F) -> DoorGraph<'a> {
+        let mut room_ptrs: Vec<u16> = sm
+            .room_mdb
+            .iter()
+            .filter(|(_, mdb)| include(mdb))
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        room_ptrs.sort_unstable();
+
+        let mut graph = DiGraph::new();
+        let mut nodes = HashMap::new();
+        let mut room_doors: HashMap<u16, Vec<DoorRef>> = HashMap::new();
+        for ptr in &room_ptrs {
+            let doors = (0..sm.room_mdb[ptr].door_list.len())
+                .map(|door_index| DoorRef {
+                    room_ptr: *ptr,
+                    door_index,
+                })
+                .collect::<Vec<_>>();
+            for door in &doors {
+                nodes.insert(*door, graph.add_node(*door));
+            }
+            room_doors.insert(*ptr, doors);
+        }
+
+        let door_map = DoorMap::new(sm);
+        for ptr in &room_ptrs {
+            let doors = &room_doors[ptr];
+            for from in doors {
+                for to in doors.iter().filter(|to| *to != from) {
+                    graph.add_edge(
+                        nodes[from],
+                        nodes[to],
+                        DoorGraphEdge::Room { room_ptr: *ptr },
+                    );
+                }
+            }
+
+            for (door, data) in doors.iter().zip(&sm.room_mdb[ptr].door_list) {
+                let dest_doors = match room_doors.get(&data.dest_room_ptr) {
+                    Some(dest_doors) => dest_doors,
+                    None => continue,
+                };
+                let targets = match door_map.reverse(door) {
+                    Some(reverse) if nodes.contains_key(&reverse) => vec![reverse],
+                    _ => dest_doors.clone(),
+                };
+                for target in targets {
+                    graph.add_edge(
+                        nodes[door],
+                        nodes[&target],
+                        DoorGraphEdge::Door(DoorEdge { door: *door, data }),
+                    );
+                }
+            }
+        }
+
+        DoorGraph { graph, nodes }
+    }
+
+    pub fn node(&self, door: &DoorRef) -> Option<NodeIndex> {
+        self.nodes.get(door).cloned()
+    }
+
+    // Returns the doors visited on a shortest path from one door to
+    // another, including both ends.  Going through a door and walking
+    // across a room count the same.
+    pub fn shortest_path(&self, from: &DoorRef, to: &DoorRef) -> Option<Vec<DoorRef>> {
+        let start = self.node(from)?;
+        let goal = self.node(to)?;
+        let (_, path) = astar(&self.graph, start, |n| n == goal, |_| 1, |_| 0)?;
+        Some(path.iter().map(|n| self.graph[*n]).collect())
+    }
+
+    // Returns the doors reachable from `start`, including `start`, in
+    // breadth first order.
+    pub fn reachable_from(&self, start: &DoorRef) -> Vec<DoorRef> {
+        let start = match self.node(start) {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+
+        let mut doors = Vec::new();
+        let mut bfs = Bfs::new(&self.graph, start);
+        while let Some(n) = bfs.next(&self.graph) {
+            doors.push(self.graph[n]);
+        }
+        doors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{add_room, door};
+    use super::super::{State, StateCondition};
+    use super::*;
+
+    // a <-> b -> c <-> d, with d in another area.  Every door has door
+    // blocks on the room's only screen.
+    fn test_data() -> SuperMetroidData {
+        let mut sm = SuperMetroidData::default();
+        add_room(&mut sm, 0x91f8, 1, &[(0, 0)], vec![door(0x92fd, 0, 0)]);
+        add_room(
+            &mut sm,
+            0x92fd,
+            1,
+            &[(0, 0), (1, 0)],
+            vec![door(0x91f8, 0, 0), door(0x93aa, 0, 0)],
+        );
+        add_room(&mut sm, 0x93aa, 1, &[(0, 0)], vec![door(0x94cc, 0, 0)]);
+        add_room(&mut sm, 0x94cc, 1, &[(0, 0)], vec![door(0x93aa, 0, 0)]).area = Area::Brinstar;
+        sm
+    }
+
+    #[test]
+    fn path_queries() {
+        let sm = test_data();
+        let graph = RoomGraph::new(&sm);
+
+        assert_eq!(
+            graph.shortest_path(0x91f8, 0x94cc),
+            Some(vec![
+                DoorRef {
+                    room_ptr: 0x91f8,
+                    door_index: 0
+                },
+                DoorRef {
+                    room_ptr: 0x92fd,
+                    door_index: 1
+                },
+                DoorRef {
+                    room_ptr: 0x93aa,
+                    door_index: 0
+                },
+            ])
+        );
+        assert_eq!(graph.shortest_path(0x94cc, 0x91f8), None);
+        assert_eq!(graph.reachable_from(0x93aa), vec![0x93aa, 0x94cc]);
+
+        let mut components = graph.strongly_connected_components();
+        components.sort();
+        assert_eq!(components, vec![vec![0x91f8, 0x92fd], vec![0x93aa, 0x94cc]]);
+    }
+
+    #[test]
+    fn entry_state_uses_door() {
+        let mut sm = test_data();
+        sm.room_mdb.get_mut(&0x91f8).unwrap().door_list[0].door_ptr = 0x8916;
+        let dest = sm.room_mdb.get_mut(&0x92fd).unwrap();
+        let mut state = State {
+            condition: StateCondition::DoorPointerIs { value: 0x8916 },
+            data: dest.states[0].data.clone(),
+        };
+        state.data.level_data += 1;
+        dest.states.insert(0, state);
+
+        let graph = RoomGraph::new(&sm);
+        let progress = GameProgress::default();
+        let from_a = DoorRef {
+            room_ptr: 0x91f8,
+            door_index: 0,
+        };
+        assert_eq!(graph.entry_state(&from_a, &progress), Some(0));
+        assert_eq!(sm.room_mdb[&0x92fd].state_for(&progress, None), 1);
+    }
+
+    #[test]
+    fn area_subgraph() {
+        let sm = test_data();
+        let graph = RoomGraph::for_area(&sm, Area::Crateria);
+
+        assert_eq!(graph.graph.node_count(), 3);
+        assert_eq!(graph.graph.edge_count(), 3);
+        assert!(graph.node(0x94cc).is_none());
+        assert_eq!(graph.reachable_from(0x91f8), vec![0x91f8, 0x92fd, 0x93aa]);
+    }
+
+    #[test]
+    fn door_paths_cross_rooms() {
+        let sm = test_data();
+        let graph = DoorGraph::new(&sm);
+        let d = |room_ptr, door_index| DoorRef {
+            room_ptr,
+            door_index,
+        };
+
+        // Four rooms with five doors, two of which are in room b.
+        assert_eq!(graph.graph.node_count(), 5);
+        // Entering b from a comes out of b's door back to a, then b is
+        // crossed to its door into c.
+        assert_eq!(
+            graph.shortest_path(&d(0x91f8, 0), &d(0x93aa, 0)),
+            Some(vec![d(0x91f8, 0), d(0x92fd, 0), d(0x92fd, 1), d(0x93aa, 0)])
+        );
+        assert_eq!(graph.shortest_path(&d(0x94cc, 0), &d(0x91f8, 0)), None);
+        assert_eq!(
+            graph.reachable_from(&d(0x93aa, 0)),
+            vec![d(0x93aa, 0), d(0x94cc, 0)]
+        );
+        assert_eq!(
+            DoorGraph::for_area(&sm, Area::Brinstar).graph.edge_count(),
+            0
+        );
+    }
+}
@@ -1,6 +1,13 @@
 use failure::{format_err, Error};
+use serde::Serialize;
 
-use super::{Color, Palette, RoomData, RoomMdb, TileTable, TileTableEntry, Tiles, PALETTE_ENTRIES};
+use super::buffer::{IndexedBuffer, RgbaBuffer, TileTarget};
+use super::fx::{self, RoomFrame};
+use super::{
+    fx::{AnimatedTiles, PaletteFx, Timeline},
+    rommap, Color, Palette, RoomData, RoomMdb, SuperMetroidData, TileSet, TileTable,
+    TileTableEntry, Tiles, PALETTE_ENTRIES,
+};
 
 pub const CRE_INDEX_START: u16 = 0x280;
 pub const TILE_H: usize = 8;
@@ -27,45 +34,150 @@ pub const BYTES_PER_TILE: usize = (TILE_H * TILE_W) / 2;
 //
 //  Bitplanes 1 and 2 are stored first, intertwined row by row.  Then bitplanes 3 and 4
 //  are stored, intertwined row by row.
-fn get_pixel(data: &[u8], x: u32, y: u32) -> u8 {
-    let x_shift = (7 - x) as u8;
-    let mut b = 0;
-    for bit in 0..4 {
-        let offset = y * 2 + (bit & 0x1) + ((bit >> 1) * 16);
-        if (data[offset as usize] & (1 << x_shift)) != 0 {
-            b |= 1 << bit;
+//
+// 2bpp tiles are the first 16 bytes of that layout and 8bpp tiles continue it
+// with bitplanes 5 and 6 then 7 and 8.  Mode 7 tiles are not planar at all:
+// they are one byte per pixel, row by row.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum TileFormat {
+    Bpp2,
+    Bpp4,
+    Bpp8,
+    Mode7,
+}
+
+impl TileFormat {
+    pub fn bits_per_pixel(&self) -> usize {
+        match self {
+            TileFormat::Bpp2 => 2,
+            TileFormat::Bpp4 => 4,
+            TileFormat::Bpp8 | TileFormat::Mode7 => 8,
         }
     }
-    b
-}
-pub fn de_planar_tiles(data: &mut [u8]) {
-    const BYTES_PER_TILE: usize = (8 * 8) / 2;
-    let num_tiles = data.len() / BYTES_PER_TILE;
 
-    for tile_num in 0..num_tiles {
-        let tile_data = &mut data[(tile_num as usize * BYTES_PER_TILE)..];
+    pub fn colors(&self) -> usize {
+        1 << self.bits_per_pixel()
+    }
+
+    // Tiles take the same space planar and packed.
+    pub fn bytes_per_tile(&self) -> usize {
+        TILE_W * TILE_H * self.bits_per_pixel() / 8
+    }
+
+    // Returns a pixel of a packed tile.  Packed tiles store pixels row by
+    // row with the leftmost pixel in the low bits of each byte.
+    pub fn get_pixel(&self, tile: &[u8], x: usize, y: usize) -> u8 {
+        let bpp = self.bits_per_pixel();
+        let bit = (y * TILE_W + x) * bpp;
+        let mask = ((1u16 << bpp) - 1) as u8;
+        (tile[bit / 8] >> (bit % 8)) & mask
+    }
 
-        let mut new_data = [0; BYTES_PER_TILE];
+    fn set_pixel(&self, tile: &mut [u8], x: usize, y: usize, val: u8) {
+        let bpp = self.bits_per_pixel();
+        let bit = (y * TILE_W + x) * bpp;
+        tile[bit / 8] |= val << (bit % 8);
+    }
 
-        for y in 0..8 {
-            for x in 0..8 {
-                let val = get_pixel(tile_data, x, y);
-                new_data[(y * 4 + x / 2) as usize] |= if x & 0x1 == 0x1 { val << 4 } else { val }
+    fn planar_offset(y: usize, plane: usize) -> usize {
+        y * 2 + (plane & 0x1) + ((plane >> 1) * 16)
+    }
+
+    // Converts planar tiles in place to packed tiles.
+    pub fn de_planarize(&self, data: &mut [u8]) {
+        if *self == TileFormat::Mode7 {
+            return;
+        }
+        let bytes_per_tile = self.bytes_per_tile();
+        for tile_data in data.chunks_exact_mut(bytes_per_tile) {
+            let mut new_data = vec![0; bytes_per_tile];
+            for y in 0..TILE_H {
+                for x in 0..TILE_W {
+                    let mut val = 0;
+                    for plane in 0..self.bits_per_pixel() {
+                        if tile_data[Self::planar_offset(y, plane)] & (0x80 >> x) != 0 {
+                            val |= 1 << plane;
+                        }
+                    }
+                    self.set_pixel(&mut new_data, x, y, val);
+                }
             }
+            tile_data.copy_from_slice(&new_data);
         }
+    }
 
-        for i in 0..BYTES_PER_TILE {
-            tile_data[i] = new_data[i];
+    // The inverse of `de_planarize`.
+    pub fn planarize(&self, data: &mut [u8]) {
+        if *self == TileFormat::Mode7 {
+            return;
+        }
+        let bytes_per_tile = self.bytes_per_tile();
+        for tile_data in data.chunks_exact_mut(bytes_per_tile) {
+            let mut new_data = vec![0; bytes_per_tile];
+            for y in 0..TILE_H {
+                for x in 0..TILE_W {
+                    let val = self.get_pixel(tile_data, x, y);
+                    for plane in 0..self.bits_per_pixel() {
+                        if val & (1 << plane) != 0 {
+                            new_data[Self::planar_offset(y, plane)] |= 0x80 >> x;
+                        }
+                    }
+                }
+            }
+            tile_data.copy_from_slice(&new_data);
         }
     }
 }
 
+pub fn de_planar_tiles(data: &mut [u8]) {
+    TileFormat::Bpp4.de_planarize(data);
+}
+
+// The inverse of `de_planar_tiles`.  Converts packed 4bpp tiles back into
+// the SNES planar format.
+pub fn planarize_tiles(data: &mut [u8]) {
+    TileFormat::Bpp4.planarize(data);
+}
+
+// Renders packed tiles as a sheet `tiles_w` tiles wide.  Useful for graphics
+// outside of rooms such as the HUD, fonts and mode 7 data.  Fails if a pixel
+// uses a color past the end of `colors` or the sheet has no width.
+pub fn render_tiles(
+    format: TileFormat,
+    data: &[u8],
+    colors: &[Color],
+    tiles_w: usize,
+) -> Result<RgbaBuffer, Error> {
+    if tiles_w == 0 {
+        return Err(format_err!("tile sheet must be at least one tile wide"));
+    }
+    let num_tiles = data.len() / format.bytes_per_tile();
+    let tiles_h = num_tiles.div_ceil(tiles_w);
+    let mut img = RgbaBuffer::new(tiles_w * TILE_W, tiles_h * TILE_H);
+    for (i, tile) in data.chunks_exact(format.bytes_per_tile()).enumerate() {
+        TileRenderer::render_tile_format(
+            format,
+            tile,
+            &mut img,
+            colors,
+            0,
+            i % tiles_w * TILE_W,
+            i / tiles_w * TILE_H,
+            false,
+            false,
+        )?;
+    }
+    Ok(img)
+}
+
 pub struct TileRenderer<'a> {
     num_tiles: usize,
     cre_tiles: usize,
     sce_tiles: usize,
     graphics_sheet: Vec<u8>,
-    palette: &'a Palette,
+    base_palette: &'a Palette,
+    // `base_palette` with palette FX applied.
+    palette: Palette,
     cre_table: &'a TileTable,
     sce_table: &'a TileTable,
 }
@@ -98,12 +210,41 @@ impl<'a> TileRenderer<'a> {
             cre_tiles: cre_tiles,
             sce_tiles: sce_tiles,
             graphics_sheet: graphics_sheet,
-            palette: palette,
+            base_palette: palette,
+            palette: palette.clone(),
             cre_table: cre_table,
             sce_table: sce_table,
         })
     }
 
+    // Creates a renderer for one of the game's tile sets combined with the
+    // common room elements.
+    pub fn for_tile_set(
+        sm: &'a SuperMetroidData,
+        tile_set: TileSet,
+    ) -> Result<TileRenderer<'a>, Error> {
+        let missing = || format_err!("tile set {:?} not loaded", tile_set);
+        let set = sm.tile_sets.get(tile_set as usize).ok_or_else(missing)?;
+        let cre_tiles = sm
+            .tiles
+            .get(&crate::rom_addr_to_snes!(rommap::CRE_TILES))
+            .ok_or_else(missing)?;
+        let cre_table = sm
+            .tile_tables
+            .get(&crate::rom_addr_to_snes!(rommap::CRE_TILE_TABLE))
+            .ok_or_else(missing)?;
+
+        Self::new(
+            cre_tiles,
+            sm.tiles.get(&set.tiles_ptr).ok_or_else(missing)?,
+            sm.palettes.get(&set.palette_ptr).ok_or_else(missing)?,
+            cre_table,
+            sm.tile_tables
+                .get(&set.tile_table_ptr)
+                .ok_or_else(missing)?,
+        )
+    }
+
     pub fn get_tile(self: &Self, index: u16) -> Result<&[u8], Error> {
         if index as usize >= self.num_tiles {
             return Err(format_err!("tile {} out of range.", index));
@@ -112,44 +253,107 @@ impl<'a> TileRenderer<'a> {
         Ok(&self.graphics_sheet[(index as usize * BYTES_PER_TILE)..])
     }
 
-    pub fn get_pixel(data: &[u8], x: usize, y: usize) -> u8 {
-        let b = data[(y * 4 + x / 2) as usize];
-        if x & 0x1 == 0x1 {
-            b >> 4
-        } else {
-            b & 0xf
+    // Copies the frame of an animated tiles object shown at `tick` over the
+    // tiles it replaces.  Tiles copied past the end of the sheet are dropped.
+    pub fn apply_animated_tiles(self: &mut Self, animated_tiles: &AnimatedTiles, tick: u32) {
+        let frame = match animated_tiles.frame_at(tick) {
+            Some(frame) => frame,
+            None => return,
+        };
+        // VRAM addresses are in words and tiles start at VRAM 0.
+        let offset = animated_tiles.vram_addr as usize * 2;
+        for (i, b) in frame.tiles.data.iter().enumerate() {
+            match self.graphics_sheet.get_mut(offset + i) {
+                Some(dest) => *dest = *b,
+                None => break,
+            }
+        }
+    }
+
+    // Resets the palette and applies the colors of each palette FX object
+    // shown at `tick`.  Without a tick each object's representative frame is
+    // used, which suits still images.
+    pub fn apply_palette_fx(self: &mut Self, palette_fx: &[&PaletteFx], tick: Option<u32>) {
+        self.palette = self.base_palette.clone();
+        for fx in palette_fx {
+            let frame = match tick {
+                Some(tick) => fx.frame_at(tick),
+                None => fx.representative_frame(),
+            };
+            let frame = match frame {
+                Some(frame) => frame,
+                None => continue,
+            };
+            // Colors past the background palettes belong to sprites.
+            for (index, color) in &frame.colors {
+                if let Some(dest) = self.palette.colors.get_mut(*index) {
+                    *dest = color.clone();
+                }
+            }
         }
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_tile(
+    pub fn get_pixel(data: &[u8], x: usize, y: usize) -> u8 {
+        TileFormat::Bpp4.get_pixel(data, x, y)
+    }
+
+    // Renders a 4bpp tile with palette row `row` of `colors`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_tile<T: TileTarget>(
         tile: &[u8],
-        img: &mut image::RgbaImage,
+        img: &mut T,
         colors: &[Color],
+        row: u8,
         x: usize,
         y: usize,
         flip_h: bool,
         flip_v: bool,
-    ) {
-        assert!(colors.len() >= 16);
+    ) -> Result<(), Error> {
+        Self::render_tile_format(
+            TileFormat::Bpp4,
+            tile,
+            img,
+            colors,
+            row,
+            x,
+            y,
+            flip_h,
+            flip_v,
+        )
+    }
+
+    // Renders a packed tile of any format.  Pixel values index `colors`
+    // starting at palette row `row`.  Fails if a pixel's color is past the
+    // end of `colors`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_tile_format<T: TileTarget>(
+        format: TileFormat,
+        tile: &[u8],
+        img: &mut T,
+        colors: &[Color],
+        row: u8,
+        x: usize,
+        y: usize,
+        flip_h: bool,
+        flip_v: bool,
+    ) -> Result<(), Error> {
+        let base = row as usize * 16;
         for y1 in 0..TILE_H {
             for x1 in 0..TILE_W {
                 let src_x = if flip_h { 7 - x1 } else { x1 };
                 let src_y = if flip_v { 7 - y1 } else { y1 };
-                let val = Self::get_pixel(tile, src_x, src_y);
-                let color = &colors[val as usize];
-                *img.get_pixel_mut((x + x1) as u32, (y + y1) as u32) =
-                    image::Rgba([color.r, color.g, color.b, if val == 0 { 0x0 } else { 0xff }]);
+                let val = format.get_pixel(tile, src_x, src_y);
+                img.put_color(x + x1, y + y1, colors, base + val as usize, val == 0)?;
             }
         }
+        Ok(())
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_graphics_sheet(self: &Self) -> Result<image::RgbaImage, Error> {
+    pub fn render_graphics_sheet(self: &Self) -> Result<RgbaBuffer, Error> {
         let tiles_w = 16;
         let tiles_h = self.num_tiles / tiles_w;
-        let img_w = (tiles_w * TILE_W) as u32;
-        let img_h = (tiles_h * TILE_H) as u32;
+        let img_w = tiles_w * TILE_W;
+        let img_h = tiles_h * TILE_H;
 
         let mut colors = Vec::new();
         for val in 0..16 {
@@ -160,16 +364,13 @@ impl<'a> TileRenderer<'a> {
             });
         }
 
-        let mut img = image::RgbaImage::new(img_w, img_h);
-        for (_, _, pixel) in img.enumerate_pixels_mut() {
-            *pixel = image::Rgba([0, 0, 0, 0]);
-        }
+        let mut img = RgbaBuffer::new(img_w, img_h);
 
         for i in 0..self.sce_tiles {
             let tile = self.get_tile(i as u16)?;
             let x = i % tiles_w * 8;
             let y = i / tiles_w * 8;
-            Self::render_tile(tile, &mut img, &colors, x, y, false, false);
+            Self::render_tile(tile, &mut img, &colors, 0, x, y, false, false)?;
         }
 
         for ci in 0..self.cre_tiles {
@@ -177,13 +378,12 @@ impl<'a> TileRenderer<'a> {
             let tile = self.get_tile(i as u16)?;
             let x = i % tiles_w * 8;
             let y = i / tiles_w * 8;
-            Self::render_tile(tile, &mut img, &colors, x, y, false, false);
+            Self::render_tile(tile, &mut img, &colors, 0, x, y, false, false)?;
         }
         Ok(img)
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_palette(self: &Self) -> Result<image::RgbaImage, Error> {
+    pub fn render_palette(self: &Self) -> Result<RgbaBuffer, Error> {
         let entry_w = 16;
         let entry_h = 16;
         let entries_w = 16;
@@ -191,10 +391,7 @@ impl<'a> TileRenderer<'a> {
         let img_w = entry_w * entries_w;
         let img_h = entry_h * entries_h;
 
-        let mut img = image::RgbaImage::new(img_w as u32, img_h as u32);
-        for (_, _, pixel) in img.enumerate_pixels_mut() {
-            *pixel = image::Rgba([0, 0, 0, 0]);
-        }
+        let mut img = RgbaBuffer::new(img_w, img_h);
 
         for y in 0..entries_h {
             for x in 0..entries_w {
@@ -202,10 +399,11 @@ impl<'a> TileRenderer<'a> {
                 let color = &self.palette.colors[entry];
                 for y1 in 0..entry_h {
                     for x1 in 0..entry_w {
-                        *img.get_pixel_mut(
-                            ((x * entry_w) + x1) as u32,
-                            ((y * entry_h) + y1) as u32,
-                        ) = image::Rgba([color.r, color.g, color.b, 0xff]);
+                        img.put_pixel(
+                            (x * entry_w) + x1,
+                            (y * entry_h) + y1,
+                            [color.r, color.g, color.b, 0xff],
+                        );
                     }
                 }
             }
@@ -214,10 +412,9 @@ impl<'a> TileRenderer<'a> {
         Ok(img)
     }
 
-    #[cfg(feature = "render")]
-    fn render_sub_table(
+    fn render_sub_table<T: TileTarget>(
         self: &Self,
-        img: &mut image::RgbaImage,
+        img: &mut T,
         table: &TileTable,
         offset_x: usize,
         offset_y: usize,
@@ -239,36 +436,45 @@ impl<'a> TileRenderer<'a> {
             Self::render_tile(
                 tile,
                 img,
-                &self.palette.colors[(entry.palette as usize * 16)..],
+                &self.palette.colors,
+                entry.palette,
                 x,
                 y,
                 entry.flip_h,
                 entry.flip_v,
-            );
+            )?;
         }
 
         Ok(())
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_tile_table(self: &Self) -> Result<image::RgbaImage, Error> {
+    fn tile_table_size(self: &Self) -> (usize, usize) {
         let num_entries = self.cre_table.entries.len() + self.sce_table.entries.len();
         let tiles_w = 64;
         let tiles_h = num_entries / tiles_w;
-        // a super tile is 2x2 tiles.
-        let img_w = (tiles_w * TILE_W) as u32;
-        let img_h = (tiles_h * TILE_H) as u32;
-
-        let mut img = image::RgbaImage::new(img_w, img_h);
-        for (_, _, pixel) in img.enumerate_pixels_mut() {
-            *pixel = image::Rgba([0, 0, 0, 0]);
-        }
+        (tiles_w * TILE_W, tiles_h * TILE_H)
+    }
 
-        self.render_sub_table(&mut img, &self.cre_table, 0, 0)?;
+    fn draw_tile_table<T: TileTarget>(self: &Self, img: &mut T) -> Result<(), Error> {
+        let tiles_w = 64;
+        self.render_sub_table(img, &self.cre_table, 0, 0)?;
 
         let offset_y = self.cre_table.entries.len() / tiles_w * TILE_H;
-        self.render_sub_table(&mut img, &self.sce_table, 0, offset_y)?;
+        self.render_sub_table(img, &self.sce_table, 0, offset_y)
+    }
 
+    pub fn render_tile_table(self: &Self) -> Result<RgbaBuffer, Error> {
+        let (img_w, img_h) = self.tile_table_size();
+        let mut img = RgbaBuffer::new(img_w, img_h);
+        self.draw_tile_table(&mut img)?;
+        Ok(img)
+    }
+
+    // Like `render_tile_table` but keeps the palette index of every pixel.
+    pub fn render_tile_table_indexed(self: &Self) -> Result<IndexedBuffer, Error> {
+        let (img_w, img_h) = self.tile_table_size();
+        let mut img = IndexedBuffer::new(img_w, img_h, &self.palette.colors);
+        self.draw_tile_table(&mut img)?;
         Ok(img)
     }
 
@@ -285,10 +491,9 @@ impl<'a> TileRenderer<'a> {
         }
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_block(
+    pub fn render_block<T: TileTarget>(
         self: &Self,
-        img: &mut image::RgbaImage,
+        img: &mut T,
         index: u16,
         x: usize,
         y: usize,
@@ -308,48 +513,49 @@ impl<'a> TileRenderer<'a> {
             Self::render_tile(
                 tile,
                 img,
-                &self.palette.colors[(entry.palette as usize * 16)..],
+                &self.palette.colors,
+                entry.palette,
                 x + x_offsets[sub_tile],
                 y + y_offsets[sub_tile],
                 entry.flip_h ^ flip_h,
                 entry.flip_v ^ flip_v,
-            );
+            )?;
         }
 
         Ok(())
     }
 
-    #[cfg(feature = "render")]
-    pub fn render_room(
+    fn room_size(mdb: &RoomMdb) -> (usize, usize) {
+        // Each screen is 16x16 blocks of 16x16 pixels.
+        (mdb.width as usize * 256, mdb.height as usize * 256)
+    }
+
+    fn draw_room<T: TileTarget>(
         self: &Self,
-        state: usize,
+        img: &mut T,
         mdb: &RoomMdb,
         data: &RoomData,
-    ) -> Result<image::RgbaImage, Error> {
+    ) -> Result<(), Error> {
         let block_h = 16;
         let block_w = 16;
         let super_block_h = 16;
         let super_block_w = 16;
-        let img_w = (mdb.width as usize * (super_block_w * block_w)) as u32;
-        let img_h = (mdb.height as usize * (super_block_h * block_h)) as u32;
         let room_blocks_w = mdb.width as usize * super_block_w;
         let room_blocks_h = mdb.height as usize * super_block_h;
 
-        let mut img = image::RgbaImage::new(img_w, img_h);
-        for (_, _, pixel) in img.enumerate_pixels_mut() {
-            *pixel = image::Rgba([0, 0, 0, 0]);
-        }
-
-        for (i, block) in data.layer_1.iter().enumerate() {
+        // Level data that does not match the room size is reported in
+        // `SuperMetroidData::validation`.  Only the blocks inside the room are
+        // drawn.
+        for (i, block) in data
+            .layer_1
+            .iter()
+            .take(room_blocks_w * room_blocks_h)
+            .enumerate()
+        {
             let x = i % room_blocks_w;
             let y = i / room_blocks_w;
-            if y >= room_blocks_h as usize {
-                // Bowling Alley and Double Chamber have too much data.  It is
-                // unclear why.
-                break;
-            }
             self.render_block(
-                &mut img,
+                img,
                 block.tile_index,
                 x * block_w,
                 y * block_h,
@@ -357,6 +563,133 @@ impl<'a> TileRenderer<'a> {
                 block.y_flip,
             )?;
         }
+        Ok(())
+    }
+
+    pub fn render_room(
+        self: &Self,
+        state: usize,
+        mdb: &RoomMdb,
+        data: &RoomData,
+    ) -> Result<RgbaBuffer, Error> {
+        let (img_w, img_h) = Self::room_size(mdb);
+        let mut img = RgbaBuffer::new(img_w, img_h);
+        self.draw_room(&mut img, mdb, data)?;
         Ok(img)
     }
+
+    // Renders a room as an indexed image with the current palette embedded.
+    // Each pixel is palette_row * 16 + color so edited images can be
+    // imported again with `tile_import::tiles_from_indexed`.
+    pub fn render_room_indexed(
+        self: &Self,
+        mdb: &RoomMdb,
+        data: &RoomData,
+    ) -> Result<IndexedBuffer, Error> {
+        let (img_w, img_h) = Self::room_size(mdb);
+        let mut img = IndexedBuffer::new(img_w, img_h, &self.palette.colors);
+        self.draw_room(&mut img, mdb, data)?;
+        Ok(img)
+    }
+
+    // Renders a room each time one of `animated_tiles` or `palette_fx`
+    // changes frame during the first `max_ticks` game frames.
+    pub fn render_room_animation(
+        self: &mut Self,
+        state: usize,
+        mdb: &RoomMdb,
+        data: &RoomData,
+        animated_tiles: &[&AnimatedTiles],
+        palette_fx: &[&PaletteFx],
+        max_ticks: u32,
+    ) -> Result<Vec<RoomFrame>, Error> {
+        let timelines: Vec<&dyn Timeline> = animated_tiles
+            .iter()
+            .map(|a| *a as &dyn Timeline)
+            .chain(palette_fx.iter().map(|p| *p as &dyn Timeline))
+            .collect();
+        let ticks = fx::animation_ticks(&timelines, max_ticks);
+        let mut frames = Vec::with_capacity(ticks.len());
+        for (i, tick) in ticks.iter().enumerate() {
+            for a in animated_tiles {
+                self.apply_animated_tiles(a, *tick);
+            }
+            self.apply_palette_fx(palette_fx, Some(*tick));
+            let next = ticks.get(i + 1).cloned().unwrap_or(max_ticks);
+            frames.push(RoomFrame {
+                image: self.render_room(state, mdb, data)?,
+                ticks: next.saturating_sub(*tick).max(1),
+            });
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_round_trip() {
+        for format in &[
+            TileFormat::Bpp2,
+            TileFormat::Bpp4,
+            TileFormat::Bpp8,
+            TileFormat::Mode7,
+        ] {
+            let planar: Vec<u8> = (0..(format.bytes_per_tile() * 2))
+                .map(|i| (i * 37 + 11) as u8)
+                .collect();
+            let mut data = planar.clone();
+            format.de_planarize(&mut data);
+            format.planarize(&mut data);
+            assert_eq!(data, planar, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn bpp2_pixels() {
+        // Row 0: plane 0 is 0b1010_0000, plane 1 is 0b0110_0000.
+        let mut data = vec![0; TileFormat::Bpp2.bytes_per_tile()];
+        data[0] = 0xa0;
+        data[1] = 0x60;
+        TileFormat::Bpp2.de_planarize(&mut data);
+        let row: Vec<u8> = (0..4)
+            .map(|x| TileFormat::Bpp2.get_pixel(&data, x, 0))
+            .collect();
+        assert_eq!(row, vec![1, 2, 3, 0]);
+        assert_eq!(data[0], 0b00_11_10_01);
+    }
+
+    #[test]
+    fn bpp8_uses_all_planes() {
+        let mut data = vec![0; TileFormat::Bpp8.bytes_per_tile()];
+        // Pixel (0, 1) has planes 0 and 7 set.
+        data[2] = 0x80;
+        data[51] = 0x80;
+        TileFormat::Bpp8.de_planarize(&mut data);
+        assert_eq!(TileFormat::Bpp8.get_pixel(&data, 0, 1), 0x81);
+        assert_eq!(data[8], 0x81);
+
+        // Only the colors used need to be in the palette.
+        let colors: Vec<Color> = (0..0x82u16).map(Color::from_bgr555).collect();
+        let img = render_tiles(TileFormat::Bpp8, &data, &colors, 1).unwrap();
+        assert_eq!(img.get_pixel(0, 1)[3], 0xff);
+        assert!(render_tiles(TileFormat::Bpp8, &data, &colors[..0x80], 1).is_err());
+        assert!(render_tiles(TileFormat::Bpp8, &data, &colors, 0).is_err());
+
+        let mut indexed = IndexedBuffer::new(8, 8, &colors);
+        assert!(TileRenderer::render_tile_format(
+            TileFormat::Bpp8,
+            &data,
+            &mut indexed,
+            &colors,
+            15,
+            0,
+            0,
+            false,
+            false
+        )
+        .is_err());
+    }
 }
@@ -1,7 +1,28 @@
+pub mod buffer;
 pub mod compression;
+pub mod disasm;
+pub mod doors;
+pub mod enemies;
+pub mod fx;
+pub mod graph;
 pub mod graphics;
+pub mod map;
+pub mod music;
+pub mod palette;
+pub mod progress;
 pub mod rommap;
+pub mod samus;
+pub mod save;
+pub mod start;
+pub mod stations;
+pub mod symbols;
+#[cfg(test)]
+mod test_util;
+pub mod text;
+pub mod tile_import;
 mod util;
+pub mod validation;
+pub mod worldmap;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use failure::{format_err, Error};
@@ -9,11 +30,21 @@ use num::FromPrimitive;
 use num_derive::FromPrimitive;
 use serde::Serialize;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{Cursor, Read};
 
+use fx::{AnimatedTiles, FxEntry, PaletteFx};
 use graphics::de_planar_tiles;
+use map::{AreaMap, MapBits};
+use music::SongSet;
+use samus::SamusConstants;
+use stations::LoadStation;
+use symbols::SymbolTable;
+use text::Message;
 use util::RomReader;
+use validation::ValidationReport;
+
+pub use util::RomWriter;
 
 macro_rules! is_bit_set {
     ($value:expr, $test:expr) => {
@@ -21,7 +52,7 @@ macro_rules! is_bit_set {
     };
 }
 
-#[derive(Debug, FromPrimitive, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, FromPrimitive, Hash, PartialEq, Serialize)]
 #[repr(u8)]
 pub enum Area {
     Crateria = 0x00,
@@ -34,7 +65,7 @@ pub enum Area {
     Debug = 0x07,
 }
 
-#[derive(Debug, FromPrimitive, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, FromPrimitive, Hash, PartialEq, Serialize)]
 #[repr(u8)]
 pub enum Event {
     ZebesAwake = 0x00,
@@ -88,7 +119,7 @@ pub enum StateCondition {
     HasSpeedBooster,
 }
 
-#[derive(Clone, Copy, Debug, FromPrimitive, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, FromPrimitive, Hash, PartialEq, Serialize)]
 #[repr(u8)]
 pub enum TileSet {
     UpperCrateria = 0x00,
@@ -122,7 +153,7 @@ pub enum TileSet {
     DraygonRoom = 0x1c,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct StateData {
     pub level_data: u32,
     pub tile_set: TileSet,
@@ -135,10 +166,10 @@ pub struct StateData {
     pub layer_2_scroll_y: u8,
     pub scroll_ptr: u16,      // bank $8f?
     pub x_ray_block_ptr: u16, // bank ??
-    pub main_asm_ptr: u16,    // bank ??
+    pub main_asm_ptr: u16,    // bank $8f
     pub plm_ptr: u16,         // bank ??
     pub bg_ptr: u16,          // bank ??
-    pub setup_asm_ptr: u16,   // bank ??
+    pub setup_asm_ptr: u16,   // bank $8f
 }
 
 #[derive(Debug, Serialize)]
@@ -186,17 +217,41 @@ pub struct RoomData {
     pub num_doors: usize,
 }
 
+// Bits of `DoorData::elevator_props`.
+pub const DOOR_FLAG_AREA_CHANGE: u8 = 0x40;
+pub const DOOR_FLAG_ELEVATOR: u8 = 0x80;
+
 #[derive(Debug, Serialize)]
 pub struct DoorData {
+    pub door_ptr: u16,      // bank 0x83, where this door's data was loaded from
     pub dest_room_ptr: u16, // bank 0x8f
     pub elevator_props: u8,
     pub orientation: u8,
-    pub x: u16,
-    pub y: u16,
+    pub x: u16, // low byte: door cap x (blocks), high byte: screen x
+    pub y: u16, // low byte: door cap y (blocks), high byte: screen y
     pub spawn_dist: u16,
     pub asm_ptr: u16, // bank 0x8f
 }
 
+impl DoorData {
+    pub fn is_elevator(&self) -> bool {
+        is_bit_set!(self.elevator_props, DOOR_FLAG_ELEVATOR)
+    }
+
+    pub fn is_area_change(&self) -> bool {
+        is_bit_set!(self.elevator_props, DOOR_FLAG_AREA_CHANGE)
+    }
+
+    // Screen in the destination room that Samus is placed in.
+    pub fn screen_x(&self) -> u8 {
+        (self.x >> 8) as u8
+    }
+
+    pub fn screen_y(&self) -> u8 {
+        (self.y >> 8) as u8
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct RoomMdb {
     pub index: u8,
@@ -212,6 +267,16 @@ pub struct RoomMdb {
 
     pub states: Vec<State>,
     pub door_list: Vec<DoorData>,
+
+    // False for rooms that were only found by scanning bank $8f.
+    pub door_reachable: bool,
+}
+
+impl RoomMdb {
+    // The default state is always last in the state list.
+    pub fn default_state(&self) -> usize {
+        self.states.len() - 1
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -299,13 +364,13 @@ pub struct TileSetEntry {
     pub palette_ptr: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct Tiles {
     // Data is de-planarized and stored as 4bpp tiled.
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct TileTableEntry {
     index: u16,
     palette: u8,
@@ -314,21 +379,51 @@ pub struct TileTableEntry {
     flip_v: bool,
 }
 
+impl TileTableEntry {
+    pub fn from_u16(v: u16) -> TileTableEntry {
+        TileTableEntry {
+            index: v & 0x3ff,
+            palette: ((v >> 10) & 0x7) as u8,
+            priority: (v & (1 << 13)) != 0,
+            flip_h: (v & (1 << 14)) != 0,
+            flip_v: (v & (1 << 15)) != 0,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct TileTable {
     pub entries: Vec<TileTableEntry>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
 }
 
+impl Color {
+    // Converts a SNES 15 bit BGR color.
+    pub fn from_bgr555(val: u16) -> Color {
+        Color {
+            r: ((val & 0x1f) << 3) as u8,
+            g: (((val >> 5) & 0x1f) << 3) as u8,
+            b: (((val >> 10) & 0x1f) << 3) as u8,
+        }
+    }
+
+    // The inverse of `from_bgr555`.  Each component is rounded to the
+    // nearest 5 bit value.
+    pub fn to_bgr555(&self) -> u16 {
+        let component = |c: u8| ((c as u16 + 4) >> 3).min(31);
+        component(self.r) | component(self.g) << 5 | component(self.b) << 10
+    }
+}
+
 pub const PALETTE_ENTRIES: usize = 16 * 8;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Palette {
     colors: Vec<Color>,
 }
@@ -372,12 +467,28 @@ pub struct EnemyData {
 pub struct Enemy {
     pub addr: u16,
     pub data: EnemyData,
+    // Debug name from the ROM.  Empty if the enemy has none, see
+    // `display_name`.
     pub name: String,
+    // Symbols for the header's routine pointers, by field name.  Filled in
+    // by `SuperMetroidData::resolve_symbols`.
+    pub ai_symbols: BTreeMap<&'static str, String>,
 }
 
+// An object that could not be decoded.  It is left out of
+// `SuperMetroidData` so the rest of the ROM still loads.
 #[derive(Debug, Serialize)]
+pub struct SkippedObject {
+    pub kind: &'static str,
+    pub ptr: u32,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct SuperMetroidData {
     pub room_mdb: HashMap<u16, RoomMdb>,
+    // Room pointers in the order they were discovered.
+    pub room_order: Vec<u16>,
     pub level_data: HashMap<u32, RoomData>,
     pub plm_population: HashMap<u16, Vec<PlmPopulation>>,
     pub tile_sets: Vec<TileSetEntry>,
@@ -385,11 +496,50 @@ pub struct SuperMetroidData {
     pub tile_tables: HashMap<u32, TileTable>,
     pub palettes: HashMap<u32, Palette>,
     pub enemies: HashMap<u16, Enemy>,
+    // FX lists by `StateData::fx_ptr`.
+    pub fx: HashMap<u16, Vec<FxEntry>>,
+    // Animated tiles object pointers selected by each bit of
+    // `FxEntry::animated_tiles`.
+    pub animated_tiles_table: Vec<u16>,
+    pub animated_tiles: HashMap<u16, AnimatedTiles>,
+    // Palette FX object pointers for each tile set selected by each bit of
+    // `FxEntry::palette_fx`.
+    pub palette_fx_table: Vec<Vec<u16>>,
+    pub palette_fx: HashMap<u16, PaletteFx>,
+    pub area_maps: Vec<AreaMap>,
+    // Message boxes in order, starting with message 1.
+    pub messages: Vec<Message>,
+    // None if the projectile tables could not be followed.
+    pub samus: Option<SamusConstants>,
+    // Song sets in data index order, starting with the SPC engine at index
+    // 0.  Sets that could not be followed are in `skipped`.
+    pub song_sets: Vec<SongSet>,
+    // Save and load stations of every area in table order.
+    pub load_stations: Vec<LoadStation>,
+    pub map_tiles: Tiles,
+    pub map_palette: Palette,
+    pub validation: ValidationReport,
+    pub skipped: Vec<SkippedObject>,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadOptions {
+    // Scan bank $8f for room headers that can not be reached through doors
+    // from the starting room.  These include debug rooms and cut content.
+    pub scan_unreachable_rooms: bool,
+}
+
+// Shared data first loaded while loading a room.
+#[derive(Default)]
+struct RoomAdditions {
+    level_data: Vec<u32>,
+    plm_lists: Vec<u16>,
 }
 
 struct Loader<'a> {
     rom_data: &'a [u8],
-    rooms_to_check: HashSet<u16>,
+    rooms_to_check: VecDeque<u16>,
+    rooms_queued: HashSet<u16>,
     sm: SuperMetroidData,
 }
 
@@ -397,9 +547,11 @@ impl<'a> Loader<'a> {
     pub fn new(rom_data: &'a [u8]) -> Loader {
         let mut loader = Loader {
             rom_data: rom_data,
-            rooms_to_check: HashSet::new(),
+            rooms_to_check: VecDeque::new(),
+            rooms_queued: HashSet::new(),
             sm: SuperMetroidData {
                 room_mdb: HashMap::new(),
+                room_order: Vec::new(),
                 level_data: HashMap::new(),
                 plm_population: HashMap::new(),
                 tile_sets: Vec::new(),
@@ -407,12 +559,70 @@ impl<'a> Loader<'a> {
                 tile_tables: HashMap::new(),
                 palettes: HashMap::new(),
                 enemies: HashMap::new(),
+                fx: HashMap::new(),
+                animated_tiles_table: Vec::new(),
+                animated_tiles: HashMap::new(),
+                palette_fx_table: Vec::new(),
+                palette_fx: HashMap::new(),
+                area_maps: Vec::new(),
+                messages: Vec::new(),
+                samus: None,
+                load_stations: Vec::new(),
+                song_sets: Vec::new(),
+                map_tiles: Tiles::default(),
+                map_palette: Palette::default(),
+                validation: ValidationReport::default(),
+                skipped: Vec::new(),
             },
         };
+        loader.queue_room(rom_addr_to_snes16!(rommap::ROOM_MDB_START));
         loader
-            .rooms_to_check
-            .insert((rommap::ROOM_MDB_START & 0x7fff) as u16 + 0x8000);
-        loader
+    }
+
+    // Returns the ROM from `offset` on.
+    fn rom_from(&self, offset: usize) -> Result<&'a [u8], Error> {
+        self.rom_data
+            .get(offset..)
+            .ok_or_else(|| format_err!("offset {:x} is past the end of the ROM", offset))
+    }
+
+    // Returns the ROM from a pointer into `bank` on.
+    fn bank_data(&self, bank: usize, ptr: u16) -> Result<&'a [u8], Error> {
+        if ptr < 0x8000 {
+            return Err(format_err!("bad pointer {:02x}:{:04x}", bank, ptr));
+        }
+        self.rom_from(rom_addr!(bank, ptr))
+    }
+
+    // Returns compressed level data, checking that it is in a level data
+    // bank.
+    fn level_data_from(&self, level_data_ptr: u32) -> Result<&'a [u8], Error> {
+        let bank = (level_data_ptr >> 16) as usize;
+        if !(rommap::LEVEL_DATA_BANK_START..=rommap::LEVEL_DATA_BANK_END).contains(&bank) {
+            return Err(format_err!("bad level data pointer {:06x}", level_data_ptr));
+        }
+        self.bank_data(bank, level_data_ptr as u16)
+    }
+
+    fn skip(self: &mut Self, kind: &'static str, ptr: u32, error: Error) {
+        self.sm.skipped.push(SkippedObject {
+            kind,
+            ptr,
+            error: error.to_string(),
+        });
+    }
+
+    fn is_skipped(&self, kind: &str, ptr: u16) -> bool {
+        self.sm
+            .skipped
+            .iter()
+            .any(|s| s.kind == kind && s.ptr == ptr as u32)
+    }
+
+    fn queue_room(self: &mut Self, room_ptr: u16) {
+        if self.rooms_queued.insert(room_ptr) {
+            self.rooms_to_check.push_back(room_ptr);
+        }
     }
 
     fn load_room_mdb_header(data: &[u8]) -> Result<RoomMdb, Error> {
@@ -430,6 +640,7 @@ impl<'a> Loader<'a> {
             door_list_ptr: r.read_u16::<LittleEndian>()?,
             states: Vec::new(),
             door_list: Vec::new(),
+            door_reachable: true,
         })
     }
 
@@ -487,7 +698,7 @@ impl<'a> Loader<'a> {
 
     fn load_states(self: &Self, state_offset: usize, states: &mut Vec<State>) -> Result<(), Error> {
         // Create a mutable shadow so we can increment state_offset in this function's scope.
-        let mut r = Cursor::new(&self.rom_data[state_offset..]);
+        let mut r = Cursor::new(self.rom_from(state_offset)?);
         loop {
             let condition = Self::load_state_condition(&mut r)?;
 
@@ -503,10 +714,9 @@ impl<'a> Loader<'a> {
                 // For all other conditions, the state data is pointed to by the next u16.
                 _ => (r.read_u16::<LittleEndian>()?, false),
             };
-
             states.push(State {
                 condition: condition,
-                data: Self::load_state_data(&self.rom_data[rom_addr!(0x8f, data_ptr)..])?,
+                data: Self::load_state_data(self.bank_data(0x8f, data_ptr)?)?,
             });
             if done {
                 break;
@@ -516,9 +726,9 @@ impl<'a> Loader<'a> {
         Ok(())
     }
 
-    fn load_room_mdb(self: &mut Self, offset: usize) -> Result<RoomMdb, Error> {
-        let mut mdb = Self::load_room_mdb_header(&self.rom_data[offset..])?;
-        self.load_states(offset + 0xb, &mut mdb.states)?;
+    fn load_room_mdb(self: &mut Self, room_ptr: u16) -> Result<RoomMdb, Error> {
+        let mut mdb = Self::load_room_mdb_header(self.bank_data(0x8f, room_ptr)?)?;
+        self.load_states(rom_addr!(0x8f, room_ptr) + 0xb, &mut mdb.states)?;
 
         Ok(mdb)
     }
@@ -536,6 +746,9 @@ impl<'a> Loader<'a> {
     }
 
     fn load_room_data(data: &[u8]) -> Result<RoomData, Error> {
+        if data.len() < 2 {
+            return Err(format_err!("room data too short: {} bytes", data.len()));
+        }
         let data_len = data.len() - 2;
         let mut r = Cursor::new(data);
 
@@ -588,7 +801,7 @@ impl<'a> Loader<'a> {
         })
     }
 
-    fn load_door_data(data: &[u8]) -> Result<DoorData, Error> {
+    fn load_door_data(door_ptr: u16, data: &[u8]) -> Result<DoorData, Error> {
         let mut r = Cursor::new(data);
         let dest_room_ptr = r.read_u16::<LittleEndian>()?;
         let elevator_props = r.read_u8()?;
@@ -601,6 +814,7 @@ impl<'a> Loader<'a> {
         let asm_ptr = r.read_u16::<LittleEndian>()?;
 
         Ok(DoorData {
+            door_ptr: door_ptr,
             dest_room_ptr: dest_room_ptr,
             elevator_props: elevator_props,
             orientation: orientation,
@@ -612,16 +826,17 @@ impl<'a> Loader<'a> {
     }
 
     fn get_or_load_level_data(self: &mut Self, level_data_ptr: u32) -> Result<&RoomData, Error> {
-        Ok(self.sm.level_data.entry(level_data_ptr).or_insert({
-            let level_data =
-                compression::decompress(&self.rom_data[snes_to_rom_addr!(level_data_ptr)..])?;
-            Self::load_room_data(&level_data)?
-        }))
+        if !self.sm.level_data.contains_key(&level_data_ptr) {
+            let level_data = compression::decompress(self.level_data_from(level_data_ptr)?)?;
+            let room_data = Self::load_room_data(&level_data)?;
+            self.sm.level_data.insert(level_data_ptr, room_data);
+        }
+        Ok(&self.sm.level_data[&level_data_ptr])
     }
 
     fn get_or_load_plm_list(self: &mut Self, plm_ptr: u16) -> Result<&Vec<PlmPopulation>, Error> {
-        Ok(self.sm.plm_population.entry(plm_ptr).or_insert({
-            let mut r = Cursor::new(&self.rom_data[rom_addr!(0x8f, plm_ptr)..]);
+        if !self.sm.plm_population.contains_key(&plm_ptr) {
+            let mut r = Cursor::new(self.bank_data(0x8f, plm_ptr)?);
             let mut plms = Vec::new();
             loop {
                 let id = r.read_u16::<LittleEndian>()?;
@@ -635,15 +850,28 @@ impl<'a> Loader<'a> {
                     param: r.read_u16::<LittleEndian>()?,
                 });
             }
-            plms
-        }))
+            self.sm.plm_population.insert(plm_ptr, plms);
+        }
+        Ok(&self.sm.plm_population[&plm_ptr])
     }
 
-    fn load_level_data(self: &mut Self, mdb: &RoomMdb) -> Result<usize, Error> {
+    // Level data and PLM lists loaded for the first time are added to
+    // `added` so they can be removed if the room fails to load.
+    fn load_level_data(
+        self: &mut Self,
+        mdb: &RoomMdb,
+        added: &mut RoomAdditions,
+    ) -> Result<usize, Error> {
         // Load level data and calculate number of doors.
         let mut num_doors = 0;
         for state in &mdb.states {
             let level_data_ptr = state.data.level_data;
+            if !self.sm.level_data.contains_key(&level_data_ptr) {
+                added.level_data.push(level_data_ptr);
+            }
+            if !self.sm.plm_population.contains_key(&state.data.plm_ptr) {
+                added.plm_lists.push(state.data.plm_ptr);
+            }
             let level_data = self.get_or_load_level_data(level_data_ptr)?;
             num_doors = cmp::max(num_doors, level_data.num_doors);
             self.get_or_load_plm_list(state.data.plm_ptr)?;
@@ -651,20 +879,16 @@ impl<'a> Loader<'a> {
         Ok(num_doors)
     }
 
-    fn load_door_list(self: &mut Self, mdb: &mut RoomMdb, num_doors: usize) -> Result<(), Error> {
+    fn load_door_list(self: &Self, mdb: &mut RoomMdb, num_doors: usize) -> Result<(), Error> {
         // load door list.
-        let mut r = Cursor::new(&self.rom_data[rom_addr!(0x8f, mdb.door_list_ptr)..]);
+        let mut r = Cursor::new(self.bank_data(0x8f, mdb.door_list_ptr)?);
         for _ in 0..num_doors {
             let door_data_ptr = r.read_u16::<LittleEndian>()?;
-            let door_data = Self::load_door_data(&self.rom_data[rom_addr!(0x83, door_data_ptr)..])?;
-            let dest_room_ptr = door_data.dest_room_ptr;
-            if dest_room_ptr == 0 {
-                continue;
-            }
+            let door_data =
+                Self::load_door_data(door_data_ptr, self.bank_data(0x83, door_data_ptr)?)?;
+            // Doors without a destination are still kept so that the door
+            // list stays indexed by the door blocks' bts values.
             mdb.door_list.push(door_data);
-            if !self.sm.room_mdb.contains_key(&dest_room_ptr) {
-                self.rooms_to_check.insert(dest_room_ptr);
-            }
         }
 
         Ok(())
@@ -712,13 +936,7 @@ impl<'a> Loader<'a> {
         let mut r = Cursor::new(data);
         for _ in 0..num_entries {
             let v = r.read_u16::<LittleEndian>()?;
-            entries.push(TileTableEntry {
-                index: v & 0x3ff,
-                palette: ((v >> 10) & 0x7) as u8,
-                priority: (v & (1 << 13)) != 0,
-                flip_h: (v & (1 << 14)) != 0,
-                flip_v: (v & (1 << 15)) != 0,
-            });
+            entries.push(TileTableEntry::from_u16(v));
         }
 
         self.sm
@@ -735,17 +953,119 @@ impl<'a> Loader<'a> {
 
         let rom_addr = snes_to_rom_addr!(addr);
         let data = compression::decompress(&self.rom_data[(rom_addr as usize)..])?;
-        let mut r = Cursor::new(&data);
+        let palette = Self::load_palette_data(&data)?;
+        self.sm.palettes.insert(addr, palette);
+        Ok(())
+    }
+
+    fn load_palette_data(data: &[u8]) -> Result<Palette, Error> {
+        let mut r = Cursor::new(data);
         let mut colors = Vec::with_capacity(PALETTE_ENTRIES);
         for _ in 0..PALETTE_ENTRIES {
-            let val = r.read_u16::<LittleEndian>()?;
-            colors.push(Color {
-                r: (((val >> 0) & 0x1f) << 3) as u8,
-                g: (((val >> 5) & 0x1f) << 3) as u8,
-                b: (((val >> 10) & 0x1f) << 3) as u8,
-            });
+            colors.push(Color::from_bgr555(r.read_u16::<LittleEndian>()?));
         }
-        self.sm.palettes.insert(addr, Palette { colors: colors });
+        Ok(Palette { colors: colors })
+    }
+
+    fn load_area_maps(self: &mut Self) -> Result<(), Error> {
+        let mut station_ptrs = Cursor::new(&self.rom_data[rommap::MAP_STATION_REVEAL_TABLE..]);
+        for i in 0..rommap::AREA_MAP_COUNT {
+            let area = Area::from_usize(i).ok_or(format_err!("unknown area {}", i))?;
+            let tilemap_addr = rommap::AREA_MAP_TILEMAPS + i * rommap::AREA_MAP_TILEMAP_SIZE;
+            let mut map = AreaMap::from_tilemap(area, &self.rom_data[tilemap_addr..])?;
+
+            let station_ptr = station_ptrs.read_u16::<LittleEndian>()?;
+            if station_ptr >= 0x8000 {
+                map.station_reveal = MapBits::from_bytes(
+                    &self.rom_data[rom_addr!(rommap::MAP_STATION_REVEAL_BANK, station_ptr)..],
+                )?;
+            }
+            self.sm.area_maps.push(map);
+        }
+
+        let mut tiles =
+            self.rom_data[rommap::MAP_TILES..(rommap::MAP_TILES + rommap::MAP_TILES_SIZE)].to_vec();
+        de_planar_tiles(&mut tiles);
+        self.sm.map_tiles = Tiles { data: tiles };
+        self.sm.map_palette = Self::load_palette_data(&self.rom_data[rommap::MAP_PALETTE..])?;
+
+        Ok(())
+    }
+
+    fn load_fx(self: &mut Self) -> Result<(), Error> {
+        self.sm.animated_tiles_table = fx::load_animated_tiles_table(self.rom_data)?;
+        self.sm.palette_fx_table = fx::load_palette_fx_table(self.rom_data)?;
+
+        // Palette FX bits select different objects for each tile set.
+        let mut fx_uses: Vec<(u16, TileSet)> = self
+            .sm
+            .room_mdb
+            .values()
+            .flat_map(|mdb| {
+                mdb.states
+                    .iter()
+                    .map(|state| (state.data.fx_ptr, state.data.tile_set))
+            })
+            .collect();
+        fx_uses.sort_unstable_by_key(|(fx_ptr, tile_set)| (*fx_ptr, *tile_set as u8));
+        fx_uses.dedup();
+
+        for (fx_ptr, tile_set) in fx_uses {
+            if !self.sm.fx.contains_key(&fx_ptr) {
+                // Unterminated lists are left out rather than guessed at.
+                let entries = match fx::load_fx_list(self.rom_data, fx_ptr) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        self.skip("fx list", fx_ptr as u32, e);
+                        Vec::new()
+                    }
+                };
+                self.sm.fx.insert(fx_ptr, entries);
+            }
+            let entries = self.sm.fx[&fx_ptr].clone();
+            for entry in &entries {
+                for bit in 0..fx::ANIMATED_TILES_BITS {
+                    if entry.animated_tiles & (1 << bit) == 0 {
+                        continue;
+                    }
+                    let ptr = self.sm.animated_tiles_table[bit];
+                    if ptr < 0x8000
+                        || self.sm.animated_tiles.contains_key(&ptr)
+                        || self.is_skipped("animated tiles", ptr)
+                    {
+                        continue;
+                    }
+                    match AnimatedTiles::load(self.rom_data, ptr) {
+                        Ok(animated_tiles) => {
+                            self.sm.animated_tiles.insert(ptr, animated_tiles);
+                        }
+                        Err(e) => self.skip("animated tiles", ptr as u32, e),
+                    }
+                }
+                for bit in 0..fx::PALETTE_FX_BITS {
+                    if entry.palette_fx & (1 << bit) == 0 {
+                        continue;
+                    }
+                    let ptr = match self.sm.palette_fx_table[tile_set as usize].get(bit) {
+                        Some(ptr) => *ptr,
+                        None => continue,
+                    };
+                    if ptr < 0x8000
+                        || self.sm.palette_fx.contains_key(&ptr)
+                        || self.is_skipped("palette fx", ptr)
+                    {
+                        continue;
+                    }
+                    match PaletteFx::load(self.rom_data, ptr) {
+                        Ok(palette_fx) => {
+                            self.sm.palette_fx.insert(ptr, palette_fx);
+                        }
+                        Err(e) => self.skip("palette fx", ptr as u32, e),
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -800,15 +1120,20 @@ impl<'a> Loader<'a> {
         };
         let name = if data.name_ptr != 0x0000 {
             let name_addr = rom_addr!(0xb4, data.name_ptr);
-            std::str::from_utf8(&self.rom_data[name_addr..(name_addr + 10)])?
+            let name_data = self
+                .rom_data
+                .get(name_addr..(name_addr + enemies::ENEMY_NAME_LEN))
+                .ok_or_else(|| format_err!("enemy name at {:x} out of range", name_addr))?;
+            enemies::decode_name(name_data)
         } else {
-            ""
+            String::new()
         };
 
         Ok(Some(Enemy {
             addr: rom_addr_to_snes16!(addr),
             data: data,
-            name: name.to_string(),
+            name,
+            ai_symbols: BTreeMap::new(),
         }))
     }
 
@@ -821,17 +1146,102 @@ impl<'a> Loader<'a> {
         Ok(())
     }
 
-    pub fn load(mut self: Self) -> Result<SuperMetroidData, Error> {
-        while !self.rooms_to_check.is_empty() {
-            let room_ptr = *(self.rooms_to_check.iter().next().unwrap());
-            let rom_ptr = rom_addr!(0x8f, room_ptr);
-            let mut mdb = self.load_room_mdb(rom_ptr)?;
+    fn load_room(self: &mut Self, room_ptr: u16, door_reachable: bool) -> Result<(), Error> {
+        let mut mdb = self.load_room_mdb(room_ptr)?;
+        mdb.door_reachable = door_reachable;
+
+        let mut added = RoomAdditions::default();
+        let result = self
+            .load_level_data(&mdb, &mut added)
+            .and_then(|num_doors| self.load_door_list(&mut mdb, num_doors));
+        if let Err(e) = result {
+            // Level data and PLM lists are shared between rooms so only the
+            // ones this room loaded first are removed.
+            for ptr in &added.level_data {
+                self.sm.level_data.remove(ptr);
+            }
+            for ptr in &added.plm_lists {
+                self.sm.plm_population.remove(ptr);
+            }
+            return Err(e);
+        }
+
+        // Destinations are only queued once the room is known to be good.
+        for door in &mdb.door_list {
+            if door.dest_room_ptr != 0 {
+                self.queue_room(door.dest_room_ptr);
+            }
+        }
+        self.sm.room_order.push(room_ptr);
+        self.sm.room_mdb.insert(room_ptr, mdb);
+        Ok(())
+    }
+
+    // Walks doors breadth first from the queued rooms.
+    fn load_queued_rooms(self: &mut Self, door_reachable: bool) -> Result<(), Error> {
+        while let Some(room_ptr) = self.rooms_to_check.pop_front() {
+            // Rooms found by scanning may lead to garbage.  Those are dropped
+            // rather than failing the whole load.
+            if door_reachable {
+                self.load_room(room_ptr, door_reachable)?;
+            } else if self.probe_room_mdb(room_ptr) {
+                let _ = self.load_room(room_ptr, door_reachable);
+            }
+        }
+        Ok(())
+    }
 
-            let num_doors = self.load_level_data(&mut mdb)?;
-            self.load_door_list(&mut mdb, num_doors)?;
+    // Checks that a room header, its states and their level data all parse
+    // without touching the loaded data.
+    fn probe_room_mdb(self: &Self, room_ptr: u16) -> bool {
+        let mdb = match self
+            .bank_data(0x8f, room_ptr)
+            .and_then(Self::load_room_mdb_header)
+        {
+            Ok(mdb) => mdb,
+            Err(_) => return false,
+        };
 
-            self.rooms_to_check.remove(&room_ptr);
-            self.sm.room_mdb.insert(room_ptr, mdb);
+        // Level data sizes are stored in a u16 byte count.
+        let num_blocks = mdb.width as usize * mdb.height as usize * 16 * 16;
+        if num_blocks == 0 || num_blocks * 2 > 0xffff || mdb.door_list_ptr < 0x8000 {
+            return false;
+        }
+
+        let mut states = Vec::new();
+        if self
+            .load_states(rom_addr!(0x8f, room_ptr) + 0xb, &mut states)
+            .is_err()
+        {
+            return false;
+        }
+
+        states.iter().all(|state| {
+            if state.data.plm_ptr < 0x8000 {
+                return false;
+            }
+            self.level_data_from(state.data.level_data)
+                .and_then(compression::decompress)
+                .and_then(|data| Self::load_room_data(&data))
+                .is_ok()
+        })
+    }
+
+    fn scan_unreachable_rooms(self: &mut Self) -> Result<(), Error> {
+        for room_ptr in 0x8000..=0xffffu16 {
+            if self.rooms_queued.contains(&room_ptr) || !self.probe_room_mdb(room_ptr) {
+                continue;
+            }
+            self.queue_room(room_ptr);
+            self.load_queued_rooms(false)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(mut self: Self, options: &LoadOptions) -> Result<SuperMetroidData, Error> {
+        self.load_queued_rooms(true)?;
+        if options.scan_unreachable_rooms {
+            self.scan_unreachable_rooms()?;
         }
 
         self.load_tileset_table()?;
@@ -846,7 +1256,36 @@ impl<'a> Loader<'a> {
         self.load_tiles(rom_addr_to_snes!(rommap::CRE_TILES))?;
         self.load_tile_table(rom_addr_to_snes!(rommap::CRE_TILE_TABLE))?;
 
+        self.load_fx()?;
         self.load_enemies()?;
+        self.load_area_maps()?;
+        match text::load_messages(self.rom_data, &text::Charset::message_box()) {
+            Ok(messages) => self.sm.messages = messages,
+            Err(e) => self.skip(
+                "message table",
+                rom_addr_to_snes!(rommap::MESSAGE_DEFINITIONS),
+                e,
+            ),
+        }
+        match SamusConstants::load(self.rom_data) {
+            Ok(samus) => self.sm.samus = Some(samus),
+            Err(e) => self.skip(
+                "samus constants",
+                rom_addr_to_snes!(rommap::BEAM_DAMAGE_TABLE),
+                e,
+            ),
+        }
+        for (data_index, ptr) in music::song_set_ptrs(self.rom_data)? {
+            match SongSet::load(self.rom_data, data_index, ptr) {
+                Ok(set) => self.sm.song_sets.push(set),
+                Err(e) => self.skip("song set", ptr, e),
+            }
+        }
+        let room_mdb = &self.sm.room_mdb;
+        self.sm.load_stations =
+            stations::load_stations(self.rom_data, |ptr| room_mdb.contains_key(&ptr))?;
+
+        self.sm.validation = ValidationReport::new(&self.sm);
 
         Ok(self.sm)
     }
@@ -854,6 +1293,51 @@ impl<'a> Loader<'a> {
 
 impl SuperMetroidData {
     pub fn new(rom_data: &[u8]) -> Result<SuperMetroidData, Error> {
+        Self::new_with_options(rom_data, &LoadOptions::default())
+    }
+
+    // Names enemy routine pointers using `symbols`.
+    pub fn resolve_symbols(&mut self, symbols: &SymbolTable) {
+        for enemy in self.enemies.values_mut() {
+            enemy.resolve_symbols(symbols);
+        }
+    }
+
+    // Returns the FX entry used by a room state when entering through
+    // `entry_door_ptr`.
+    pub fn fx_for(&self, state: &StateData, entry_door_ptr: Option<u16>) -> Option<&FxEntry> {
+        fx::select_fx(self.fx.get(&state.fx_ptr)?, entry_door_ptr)
+    }
+
+    // Returns the animated tiles objects an FX entry spawns.
+    pub fn animated_tiles_for(&self, fx: &FxEntry) -> Vec<&AnimatedTiles> {
+        self.animated_tiles_table
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| fx.animated_tiles & (1 << bit) != 0)
+            .filter_map(|(_, ptr)| self.animated_tiles.get(ptr))
+            .collect()
+    }
+
+    // Returns the palette FX objects an FX entry spawns in a room with the
+    // given tile set.
+    pub fn palette_fx_for(&self, tile_set: TileSet, fx: &FxEntry) -> Vec<&PaletteFx> {
+        let table = match self.palette_fx_table.get(tile_set as usize) {
+            Some(table) => table,
+            None => return Vec::new(),
+        };
+        table
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| fx.palette_fx & (1 << bit) != 0)
+            .filter_map(|(_, ptr)| self.palette_fx.get(ptr))
+            .collect()
+    }
+
+    pub fn new_with_options(
+        rom_data: &[u8],
+        options: &LoadOptions,
+    ) -> Result<SuperMetroidData, Error> {
         if rom_data.len() != 0x300000 {
             return Err(format_err!("Rom data is wrong size."));
         }
@@ -861,14 +1345,151 @@ impl SuperMetroidData {
         // TODO: verify checksum/crc/other hash.
         let loader = Loader::new(rom_data);
 
-        Ok(loader.load()?)
+        Ok(loader.load(options)?)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    // One screen of solid blocks with a door block for door 0 in the corner.
+    const LEVEL_DATA: &[u8] = &[
+        0x01, 0x00, 0x02, // size: 0x200 bytes of layer 1
+        0x01, 0x00, 0x90, // door block
+        0xe9, 0xfd, 0x00, 0x80, // 255 solid blocks
+        0xe4, 0xff, 0x00, // bts: door 0
+        0xff,
+    ];
+    const PLM_LIST: u16 = 0xb000;
+
+    // Adds room `index` at `room_ptr` with a single door to `dest_room_ptr`.
+    // `door_ptr` overrides the door list entry.
+    fn put_room(
+        rom: &mut [u8],
+        index: u16,
+        room_ptr: u16,
+        dest_room_ptr: u16,
+        door_ptr: Option<u16>,
+    ) {
+        let mut w = RomWriter::new(rom);
+        let door_list = 0xa000 + index * 0x10;
+        let door_data = door_ptr.unwrap_or(0x8000 + index * 0x10);
+        let level_data = rom_addr!(0xc2, 0x8000 + index as usize * 0x100);
+
+        let room = rom_addr!(0x8f, room_ptr);
+        w.write_bytes(room, &[index as u8, 0, 0, 0, 1, 1, 0x70, 0xa0, 0])
+            .unwrap();
+        w.write_u16(room + 9, door_list).unwrap();
+        w.write_u16(room + 11, StateConditionValue::Default as u16)
+            .unwrap();
+        let state = room + 13;
+        w.write_bytes(state, &(rom_addr_to_snes!(level_data)).to_le_bytes()[..3])
+            .unwrap();
+        w.write_u16(state + 20, PLM_LIST).unwrap();
+
+        w.write_u16(rom_addr!(0x8f, door_list), door_data).unwrap();
+        if door_data >= 0x8000 {
+            w.write_u16(rom_addr!(0x83, door_data), dest_room_ptr)
+                .unwrap();
+        }
+        w.write_bytes(level_data, LEVEL_DATA).unwrap();
+    }
+
+    #[test]
+    fn scanned_rooms_are_checked_and_rolled_back() {
+        let mut rom = vec![0; 0x300000];
+        // a <-> b are reachable from the start.  c -> d -> e and f -> g are
+        // only found by scanning.  e's door list entry is garbage and g is
+        // not a room pointer.
+        let start = rom_addr_to_snes16!(rommap::ROOM_MDB_START);
+        put_room(&mut rom, 0, start, 0x9300, None);
+        put_room(&mut rom, 1, 0x9300, start, None);
+        put_room(&mut rom, 2, 0x9500, 0x9600, None);
+        put_room(&mut rom, 3, 0x9600, 0x9700, None);
+        put_room(&mut rom, 4, 0x9700, 0x0000, Some(0x0100));
+        put_room(&mut rom, 5, 0x9800, 0x0100, None);
+
+        let mut loader = Loader::new(&rom);
+        loader.load_queued_rooms(true).unwrap();
+        assert_eq!(loader.sm.room_order, vec![start, 0x9300]);
+
+        loader.scan_unreachable_rooms().unwrap();
+        assert_eq!(
+            loader.sm.room_order,
+            vec![start, 0x9300, 0x9500, 0x9600, 0x9800]
+        );
+        assert!(!loader.sm.room_mdb[&0x9500].door_reachable);
+        assert!(!loader.sm.room_mdb.contains_key(&0x9700));
+        // e's level data was loaded before its door list failed.
+        assert_eq!(loader.sm.level_data.len(), 5);
+        assert!(!loader.sm.level_data.contains_key(&0xc2_8400));
+    }
+
+    #[test]
+    fn bad_pointers_fail_without_panicking() {
+        let mut rom = vec![0; 0x300000];
+        let start = rom_addr_to_snes16!(rommap::ROOM_MDB_START);
+        put_room(&mut rom, 0, start, 0x0100, None);
+        let mut loader = Loader::new(&rom);
+        assert!(loader.load_queued_rooms(true).is_err());
+
+        // Level data outside of the level data banks.
+        let state = rommap::ROOM_MDB_START + 13;
+        rom[state + 2] = 0x10;
+        let mut loader = Loader::new(&rom);
+        assert!(loader.load_queued_rooms(true).is_err());
+        assert!(Loader::load_room_data(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn bad_fx_objects_are_skipped() {
+        let mut rom = vec![0; 0x300000];
+        let start = rom_addr_to_snes16!(rommap::ROOM_MDB_START);
+        put_room(&mut rom, 0, start, 0x9300, None);
+        put_room(&mut rom, 1, 0x9300, start, None);
+        let mut w = RomWriter::new(&mut rom);
+        // The start room's FX uses an animated tiles object and a palette FX
+        // object with unknown instructions.  The other room's FX list is
+        // never terminated.
+        w.write_u16(rommap::ROOM_MDB_START + 13 + 6, 0x8100)
+            .unwrap();
+        w.write_u16(rom_addr!(0x8f, 0x9300) + 13 + 6, 0x8300)
+            .unwrap();
+        w.write_u16(rom_addr!(0x83, 0x8100) + 13, 0x0101).unwrap();
+        w.write_u16(rommap::FX_ANIMATED_TILES_TABLE, 0x8200)
+            .unwrap();
+        w.write_u16(rom_addr!(0x87, 0x8200), 0x8210).unwrap();
+        w.write_u16(rom_addr!(0x87, 0x8210), 0x8123).unwrap();
+        w.write_u16(rommap::PALETTE_FX_TABLE, 0xab00).unwrap();
+        w.write_u16(rom_addr!(0x89, 0xab00), 0x8400).unwrap();
+        w.write_u16(rom_addr!(0x8d, 0x8402), 0x8410).unwrap();
+        w.write_u16(rom_addr!(0x8d, 0x8410), 0x8123).unwrap();
+        for i in 0..0x20 {
+            w.write_u16(rom_addr!(0x83, 0x8300) + i * 0x10, 0x8916)
+                .unwrap();
+        }
+
+        let mut loader = Loader::new(&rom);
+        loader.load_queued_rooms(true).unwrap();
+        loader.load_fx().unwrap();
+        let skipped: Vec<(&str, u32)> = loader.sm.skipped.iter().map(|s| (s.kind, s.ptr)).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("animated tiles", 0x8200),
+                ("palette fx", 0x8400),
+                ("fx list", 0x8300)
+            ]
+        );
+        assert_eq!(loader.sm.fx[&0x8100].len(), 1);
+        assert!(loader.sm.fx[&0x8300].is_empty());
+        assert!(loader.sm.animated_tiles.is_empty());
+        assert!(loader.sm.palette_fx.is_empty());
+    }
 }
@@ -0,0 +1,277 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::buffer::RgbaBuffer;
+use super::{graphics::TileRenderer, Palette, Tiles};
+use super::{Area, RoomMdb, SuperMetroidData, TileTableEntry};
+
+// Area maps are 64x32 cells.  Each cell is one 8x8 tile on the pause screen
+// and one screen of a room.
+pub const MAP_W: usize = 64;
+pub const MAP_H: usize = 32;
+// Maps are stored as two 32x32 pages, left then right.
+pub const MAP_PAGE_W: usize = 32;
+
+// Tile used for cells that have nothing on them.
+pub const MAP_EMPTY_TILE: u16 = 0x1f;
+
+// Per cell bits in the layout the game uses for explored and map station
+// data.  Each page is 32 rows of 4 bytes with the leftmost cell in the high
+// bit.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MapBits {
+    pub data: Vec<u8>,
+}
+
+pub const MAP_BITS_SIZE: usize = MAP_W * MAP_H / 8;
+
+impl MapBits {
+    pub fn new() -> MapBits {
+        MapBits {
+            data: vec![0; MAP_BITS_SIZE],
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<MapBits, Error> {
+        if data.len() < MAP_BITS_SIZE {
+            return Err(format_err!("map bits too short: {} bytes", data.len()));
+        }
+        Ok(MapBits {
+            data: data[..MAP_BITS_SIZE].to_vec(),
+        })
+    }
+
+    fn offset(x: usize, y: usize) -> (usize, u8) {
+        let page = x / MAP_PAGE_W;
+        let page_x = x % MAP_PAGE_W;
+        (
+            page * (MAP_BITS_SIZE / 2) + y * (MAP_PAGE_W / 8) + page_x / 8,
+            0x80 >> (page_x % 8),
+        )
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        if x >= MAP_W || y >= MAP_H {
+            return false;
+        }
+        let (i, mask) = Self::offset(x, y);
+        self.data[i] & mask != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, val: bool) {
+        if x >= MAP_W || y >= MAP_H {
+            return;
+        }
+        let (i, mask) = Self::offset(x, y);
+        if val {
+            self.data[i] |= mask;
+        } else {
+            self.data[i] &= !mask;
+        }
+    }
+}
+
+impl Default for MapBits {
+    fn default() -> MapBits {
+        MapBits::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum MapCellIssue {
+    // The room covers a cell that is blank on the map.
+    EmptyCell,
+    // The room extends past the edge of the map.
+    OutOfBounds,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MapConsistencyIssue {
+    pub room_ptr: u16,
+    pub x: usize,
+    pub y: usize,
+    pub issue: MapCellIssue,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AreaMap {
+    pub area: Area,
+    // MAP_W * MAP_H entries, row major.
+    pub tiles: Vec<TileTableEntry>,
+    // Cells revealed by the area's map station.
+    pub station_reveal: MapBits,
+}
+
+impl AreaMap {
+    // Parses a pause screen tilemap.
+    pub fn from_tilemap(area: Area, data: &[u8]) -> Result<AreaMap, Error> {
+        let mut entries = Vec::with_capacity(MAP_W * MAP_H);
+        let mut r = Cursor::new(data);
+        for _ in 0..(MAP_W * MAP_H) {
+            entries.push(TileTableEntry::from_u16(r.read_u16::<LittleEndian>()?));
+        }
+
+        // Reorder the pages so that tiles are row major across the whole map.
+        let mut tiles = Vec::with_capacity(MAP_W * MAP_H);
+        for y in 0..MAP_H {
+            for x in 0..MAP_W {
+                let page = x / MAP_PAGE_W;
+                let i = page * (MAP_PAGE_W * MAP_H) + y * MAP_PAGE_W + x % MAP_PAGE_W;
+                tiles.push(entries[i].clone());
+            }
+        }
+
+        Ok(AreaMap {
+            area,
+            tiles,
+            station_reveal: MapBits::new(),
+        })
+    }
+
+    pub fn tile(&self, x: usize, y: usize) -> Option<&TileTableEntry> {
+        if x >= MAP_W || y >= MAP_H {
+            return None;
+        }
+        Some(&self.tiles[y * MAP_W + x])
+    }
+
+    // Returns the map cells covered by a room along with their tiles.  Cells
+    // off the edge of the map have no tile.
+    pub fn room_cells(&self, mdb: &RoomMdb) -> Vec<(usize, usize, Option<&TileTableEntry>)> {
+        let mut cells = Vec::new();
+        for dy in 0..mdb.height as usize {
+            for dx in 0..mdb.width as usize {
+                let x = mdb.x as usize + dx;
+                let y = mdb.y as usize + dy;
+                cells.push((x, y, self.tile(x, y)));
+            }
+        }
+        cells
+    }
+
+    // Checks that every room in this map's area lands on drawn map cells.
+    pub fn check_rooms(&self, sm: &SuperMetroidData) -> Vec<MapConsistencyIssue> {
+        let mut issues = Vec::new();
+        for room_ptr in &sm.room_order {
+            let mdb = &sm.room_mdb[room_ptr];
+            if mdb.area != self.area {
+                continue;
+            }
+            for (x, y, tile) in self.room_cells(mdb) {
+                let issue = match tile {
+                    None => MapCellIssue::OutOfBounds,
+                    Some(tile) if tile.index == MAP_EMPTY_TILE => MapCellIssue::EmptyCell,
+                    Some(_) => continue,
+                };
+                issues.push(MapConsistencyIssue {
+                    room_ptr: *room_ptr,
+                    x,
+                    y,
+                    issue,
+                });
+            }
+        }
+        issues
+    }
+
+    // Renders the map with the pause screen tiles.  If `explored` is given
+    // only explored cells are drawn.
+    pub fn render(
+        &self,
+        tiles: &Tiles,
+        palette: &Palette,
+        explored: Option<&MapBits>,
+    ) -> Result<RgbaBuffer, Error> {
+        use super::graphics::{BYTES_PER_TILE, TILE_H, TILE_W};
+
+        let mut img = RgbaBuffer::new(MAP_W * TILE_W, MAP_H * TILE_H);
+
+        for y in 0..MAP_H {
+            for x in 0..MAP_W {
+                if let Some(explored) = explored {
+                    if !explored.get(x, y) {
+                        continue;
+                    }
+                }
+                let entry = &self.tiles[y * MAP_W + x];
+                let offset = entry.index as usize * BYTES_PER_TILE;
+                if offset + BYTES_PER_TILE > tiles.data.len() {
+                    return Err(format_err!("map tile {:x} out of range", entry.index));
+                }
+                TileRenderer::render_tile(
+                    &tiles.data[offset..],
+                    &mut img,
+                    &palette.colors,
+                    entry.palette,
+                    x * TILE_W,
+                    y * TILE_H,
+                    entry.flip_h,
+                    entry.flip_v,
+                )?;
+            }
+        }
+
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::add_room;
+    use super::*;
+
+    #[test]
+    fn map_bits_layout() {
+        let mut bits = MapBits::new();
+        bits.set(0, 0, true);
+        bits.set(9, 1, true);
+        bits.set(33, 2, true);
+        assert_eq!(bits.data[0], 0x80);
+        assert_eq!(bits.data[5], 0x40);
+        assert_eq!(bits.data[0x80 + 8], 0x40);
+        assert!(bits.get(33, 2));
+        assert!(!bits.get(32, 2));
+
+        bits.set(0, 0, false);
+        assert_eq!(bits.data[0], 0x00);
+    }
+
+    #[test]
+    fn rooms_are_checked_against_map() {
+        // Left page is all empty tiles, the right page has tile 1.
+        let mut data = Vec::new();
+        for i in 0..(MAP_W * MAP_H) {
+            let tile: u16 = if i < MAP_PAGE_W * MAP_H {
+                MAP_EMPTY_TILE
+            } else {
+                0x0001
+            };
+            data.extend_from_slice(&tile.to_le_bytes());
+        }
+        let map = AreaMap::from_tilemap(Area::Crateria, &data).unwrap();
+        assert_eq!(map.tile(31, 0).unwrap().index, MAP_EMPTY_TILE);
+        assert_eq!(map.tile(32, 0).unwrap().index, 0x0001);
+
+        let mut sm = SuperMetroidData::default();
+        let room = add_room(&mut sm, 0x91f8, 2, &[], vec![]);
+        room.x = 31;
+        let room = add_room(&mut sm, 0x92fd, 2, &[], vec![]);
+        room.x = 63;
+        room.y = 4;
+
+        let issues: Vec<(u16, usize, usize, MapCellIssue)> = map
+            .check_rooms(&sm)
+            .into_iter()
+            .map(|i| (i.room_ptr, i.x, i.y, i.issue))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (0x91f8, 31, 0, MapCellIssue::EmptyCell),
+                (0x92fd, 64, 4, MapCellIssue::OutOfBounds),
+            ]
+        );
+    }
+}
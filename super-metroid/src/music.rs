@@ -0,0 +1,237 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::rommap;
+use super::StateData;
+
+// Song set data indices step by the size of a table entry.
+pub const SONG_SET_ENTRY_SIZE: usize = 3;
+// Room states use a data index or track of 0 to keep the current music.
+pub const NO_CHANGE: u8 = 0x00;
+
+// Tracks every song set has.
+const COMMON_TRACKS: &[(u8, &str)] = &[
+    (0x01, "Samus fanfare"),
+    (0x02, "Item fanfare"),
+    (0x03, "Elevator"),
+    (0x04, "Hall before statues"),
+];
+
+// Songs of the vanilla song sets by (data index, track).
+const TRACK_NAMES: &[(u8, u8, &str)] = &[
+    (0x03, 0x05, "Title sequence"),
+    (0x06, 0x05, "Empty Crateria"),
+    (0x09, 0x05, "Lower Crateria"),
+    (0x0c, 0x05, "Upper Crateria"),
+    (0x0f, 0x05, "Green Brinstar"),
+    (0x12, 0x05, "Red Brinstar"),
+    (0x15, 0x05, "Upper Norfair"),
+    (0x18, 0x05, "Lower Norfair"),
+    (0x1b, 0x05, "Outer Maridia"),
+    (0x1b, 0x06, "Inner Maridia"),
+    (0x1e, 0x05, "Tourian"),
+    (0x21, 0x05, "Mother Brain"),
+    (0x24, 0x05, "Boss fight 1"),
+    (0x27, 0x05, "Boss fight 2"),
+    (0x2a, 0x05, "Miniboss fight"),
+    (0x2d, 0x05, "Ceres"),
+    (0x30, 0x05, "Wrecked Ship (power off)"),
+    (0x30, 0x06, "Wrecked Ship (power on)"),
+    (0x33, 0x05, "Zebes boom"),
+    (0x36, 0x05, "Intro"),
+    (0x39, 0x05, "Death"),
+    (0x3c, 0x05, "Credits"),
+    (0x3f, 0x05, "The last Metroid is in captivity"),
+    (0x42, 0x05, "The galaxy is at peace"),
+    (0x45, 0x05, "Baby Metroid"),
+    (0x48, 0x05, "Samus theme"),
+];
+
+pub fn track_name(data_index: u8, track: u8) -> Option<&'static str> {
+    if let Some((_, name)) = COMMON_TRACKS.iter().find(|(t, _)| *t == track) {
+        return Some(name);
+    }
+    TRACK_NAMES
+        .iter()
+        .find(|(i, t, _)| *i == data_index && *t == track)
+        .map(|(_, _, name)| *name)
+}
+
+// Returns the track's name, or its numbers if it has none.
+pub fn describe_track(data_index: u8, track: u8) -> String {
+    if track == NO_CHANGE {
+        return "no change".to_string();
+    }
+    match track_name(data_index, track) {
+        Some(name) => name.to_string(),
+        None => format!("song set {:02x} track {:02x}", data_index, track),
+    }
+}
+
+// A run of bytes the SPC engine's loader copies into audio RAM.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpcBlock {
+    pub addr: u32, // SNES address of the block's data
+    pub aram_addr: u16,
+    pub size: u16,
+}
+
+// Music data loaded when a room state's data index changes.  The data is a
+// list of blocks, each with a size and destination header, ending with a
+// size of 0 and the address the SPC jumps to once the upload is done.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SongSet {
+    pub data_index: u8,
+    pub ptr: u32,
+    pub blocks: Vec<SpcBlock>,
+    pub entry: u16, // audio RAM address from the terminator
+}
+
+impl SongSet {
+    pub fn load(rom: &[u8], data_index: u8, ptr: u32) -> Result<SongSet, Error> {
+        let (blocks, entry) = load_spc_blocks(rom, ptr)?;
+        Ok(SongSet {
+            data_index,
+            ptr,
+            blocks,
+            entry,
+        })
+    }
+
+    // Bytes used in ROM, including block headers and the terminator.
+    pub fn rom_size(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|b| 4 + b.size as usize)
+            .sum::<usize>()
+            + 4
+    }
+}
+
+// Returns the blocks and the entry address from the terminator.
+pub fn load_spc_blocks(rom: &[u8], ptr: u32) -> Result<(Vec<SpcBlock>, u16), Error> {
+    if ptr & 0x8000 == 0 || ptr >> 16 < 0x80 {
+        return Err(format_err!("SPC data pointer {:06x} is not in ROM", ptr));
+    }
+    // LoROM banks are contiguous in the file so blocks can run from one
+    // bank into the next.
+    let mut offset = crate::rom_addr!(ptr >> 16, ptr & 0xffff);
+    let mut blocks = Vec::new();
+    loop {
+        let header = rom
+            .get(offset..offset + 4)
+            .ok_or_else(|| format_err!("SPC data at {:x} is past the end of the ROM", offset))?;
+        let mut r = Cursor::new(header);
+        let size = r.read_u16::<LittleEndian>()?;
+        let aram_addr = r.read_u16::<LittleEndian>()?;
+        if size == 0 {
+            return Ok((blocks, aram_addr));
+        }
+        offset += 4;
+        if offset + size as usize > rom.len() {
+            return Err(format_err!(
+                "SPC block at {:x} is past the end of the ROM",
+                offset
+            ));
+        }
+        blocks.push(SpcBlock {
+            addr: crate::rom_addr_to_snes!(offset),
+            aram_addr,
+            size,
+        });
+        offset += size as usize;
+    }
+}
+
+// Returns the (data index, pointer) of every entry of the song set table.
+// Entry 0 is the SPC engine, which is loaded at boot; room states use data
+// index 0 to mean "no change" rather than to reload it.
+pub fn song_set_ptrs(rom: &[u8]) -> Result<Vec<(u8, u32)>, Error> {
+    (0..rommap::SONG_SET_COUNT)
+        .map(|i| {
+            let offset = rommap::SONG_SET_TABLE + i * SONG_SET_ENTRY_SIZE;
+            let entry = rom
+                .get(offset..offset + SONG_SET_ENTRY_SIZE)
+                .ok_or_else(|| format_err!("song set table is past the end of the ROM"))?;
+            let ptr = Cursor::new(entry).read_u24::<LittleEndian>()?;
+            Ok(((i * SONG_SET_ENTRY_SIZE) as u8, ptr))
+        })
+        .collect()
+}
+
+impl StateData {
+    pub fn music_name(&self) -> String {
+        describe_track(self.music_data_index, self.music_track)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn song_sets_and_blocks_load() {
+        let mut rom = vec![0; 0x300000];
+        for i in 0..rommap::SONG_SET_COUNT {
+            let offset = rommap::SONG_SET_TABLE + i * SONG_SET_ENTRY_SIZE;
+            // Every set but the title's is empty and the last is bad.
+            let ptr: u32 = match i {
+                1 => 0xcf_fffc,
+                i if i == rommap::SONG_SET_COUNT - 1 => 0x8f_0000,
+                _ => 0xd0_9000,
+            };
+            rom[offset..offset + 3].copy_from_slice(&ptr.to_le_bytes()[..3]);
+        }
+        // The first set's first block crosses into bank $d0.
+        let data = [
+            0x06, 0x00, 0x00, 0x15, 1, 2, 3, 4, 5, 6, 0x01, 0x00, 0x00, 0x56, 7, 0x00, 0x00, 0x00,
+            0x15,
+        ];
+        let start = crate::rom_addr!(0xcf, 0xfffc);
+        rom[start..start + data.len()].copy_from_slice(&data);
+        let empty = crate::rom_addr!(0xd0, 0x9000);
+        rom[empty..empty + 4].copy_from_slice(&[0x00, 0x00, 0x34, 0x12]);
+
+        let ptrs = song_set_ptrs(&rom).unwrap();
+        assert_eq!(ptrs.len(), rommap::SONG_SET_COUNT);
+        assert_eq!(ptrs[0], (0x00, 0xd0_9000));
+        assert_eq!(ptrs[1], (0x03, 0xcf_fffc));
+        let (data_index, ptr) = ptrs[rommap::SONG_SET_COUNT - 1];
+        assert_eq!(data_index, 0x48);
+        assert!(SongSet::load(&rom, data_index, ptr).is_err());
+
+        let engine = SongSet::load(&rom, 0x00, ptrs[0].1).unwrap();
+        assert!(engine.blocks.is_empty());
+        assert_eq!(engine.entry, 0x1234);
+        assert_eq!(engine.rom_size(), 4);
+        let title = SongSet::load(&rom, 0x03, ptrs[1].1).unwrap();
+        assert_eq!(
+            title.blocks,
+            vec![
+                SpcBlock {
+                    addr: 0xd0_8000,
+                    aram_addr: 0x1500,
+                    size: 6,
+                },
+                SpcBlock {
+                    addr: 0xd0_800a,
+                    aram_addr: 0x5600,
+                    size: 1,
+                },
+            ]
+        );
+        assert_eq!(title.entry, 0x1500);
+        assert_eq!(title.rom_size(), data.len());
+
+        assert!(load_spc_blocks(&rom, 0x7e_0000).is_err());
+        // The terminator's entry address is past the end of the ROM.
+        assert!(load_spc_blocks(&rom, 0xdf_fffe).is_err());
+        assert_eq!(describe_track(0x18, 0x05), "Lower Norfair");
+        assert_eq!(describe_track(0x24, 0x03), "Elevator");
+        assert_eq!(describe_track(0x48, 0x05), "Samus theme");
+        assert_eq!(describe_track(0x48, 0x06), "song set 48 track 06");
+        assert_eq!(describe_track(0x18, NO_CHANGE), "no change");
+    }
+}
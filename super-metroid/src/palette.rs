@@ -0,0 +1,284 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use failure::{format_err, Error};
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use super::{Color, Palette};
+
+// Colors per SNES sub palette.  Exports group colors by this.
+const SUB_PALETTE_COLORS: usize = 16;
+
+const GPL_HEADER: &str = "GIMP Palette";
+const JASC_HEADER: &str = "JASC-PAL";
+const JASC_VERSION: &str = "0100";
+const TPL_MAGIC: &[u8] = b"TPL";
+// Tile Layer Pro color formats.
+const TPL_RGB: u8 = 0;
+const TPL_SNES: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteFormat {
+    // GIMP palette.
+    Gpl,
+    // Paint Shop Pro palette.
+    Jasc,
+    // Tile Layer Pro palette.
+    Tpl,
+    // Raw little endian BGR555 words as stored in CGRAM.
+    Bgr555,
+}
+
+impl PaletteFormat {
+    // Guesses the format from a file's extension.
+    pub fn from_path(path: &Path) -> Option<PaletteFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::Jasc),
+            "tpl" => Some(PaletteFormat::Tpl),
+            "bin" => Some(PaletteFormat::Bgr555),
+            _ => None,
+        }
+    }
+}
+
+// Parses a decimal color component of a text palette.
+fn parse_component(s: Option<&str>, line: &str) -> Result<u8, Error> {
+    s.ok_or_else(|| format_err!("missing color component in \"{}\"", line))?
+        .parse::<u8>()
+        .map_err(|e| format_err!("bad color component in \"{}\": {}", line, e))
+}
+
+fn parse_rgb(line: &str) -> Result<Color, Error> {
+    let mut parts = line.split_whitespace();
+    Ok(Color {
+        r: parse_component(parts.next(), line)?,
+        g: parse_component(parts.next(), line)?,
+        b: parse_component(parts.next(), line)?,
+    })
+}
+
+impl Palette {
+    pub fn from_colors(colors: Vec<Color>) -> Palette {
+        Palette { colors }
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    // Builds a palette from colors read from a file.  Colors are quantised
+    // to the 5 bit components the SNES can show.
+    fn from_imported(colors: Vec<Color>) -> Result<Palette, Error> {
+        if colors.is_empty() {
+            return Err(format_err!("palette has no colors"));
+        }
+        Ok(Palette {
+            colors: colors
+                .iter()
+                .map(|c| Color::from_bgr555(c.to_bgr555()))
+                .collect(),
+        })
+    }
+
+    pub fn export(&self, format: PaletteFormat, name: &str) -> Vec<u8> {
+        match format {
+            PaletteFormat::Gpl => self.to_gpl(name).into_bytes(),
+            PaletteFormat::Jasc => self.to_jasc().into_bytes(),
+            PaletteFormat::Tpl => self.to_tpl(),
+            PaletteFormat::Bgr555 => self.to_bgr555(),
+        }
+    }
+
+    pub fn import(format: PaletteFormat, data: &[u8]) -> Result<Palette, Error> {
+        match format {
+            PaletteFormat::Gpl => Self::from_gpl(std::str::from_utf8(data)?),
+            PaletteFormat::Jasc => Self::from_jasc(std::str::from_utf8(data)?),
+            PaletteFormat::Tpl => Self::from_tpl(data),
+            PaletteFormat::Bgr555 => Self::from_bgr555(data),
+        }
+    }
+
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", GPL_HEADER).unwrap();
+        writeln!(out, "Name: {}", name).unwrap();
+        writeln!(out, "Columns: {}", SUB_PALETTE_COLORS).unwrap();
+        writeln!(out, "#").unwrap();
+        for (i, c) in self.colors.iter().enumerate() {
+            writeln!(out, "{:3} {:3} {:3}\tIndex {}", c.r, c.g, c.b, i).unwrap();
+        }
+        out
+    }
+
+    pub fn from_gpl(text: &str) -> Result<Palette, Error> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(GPL_HEADER) {
+            return Err(format_err!("not a GIMP palette"));
+        }
+
+        let mut colors = Vec::new();
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:")
+            {
+                continue;
+            }
+            // Anything after the components is the color's name.
+            colors.push(parse_rgb(line)?);
+        }
+        Self::from_imported(colors)
+    }
+
+    pub fn to_jasc(&self) -> String {
+        let mut out = String::new();
+        // JASC palettes use CRLF line endings.
+        write!(
+            out,
+            "{}\r\n{}\r\n{}\r\n",
+            JASC_HEADER,
+            JASC_VERSION,
+            self.colors.len()
+        )
+        .unwrap();
+        for c in &self.colors {
+            write!(out, "{} {} {}\r\n", c.r, c.g, c.b).unwrap();
+        }
+        out
+    }
+
+    pub fn from_jasc(text: &str) -> Result<Palette, Error> {
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some(JASC_HEADER) {
+            return Err(format_err!("not a JASC palette"));
+        }
+        let version = lines.next();
+        if version != Some(JASC_VERSION) {
+            return Err(format_err!("unknown JASC palette version {:?}", version));
+        }
+        let count_line = lines.next().unwrap_or("");
+        let count = count_line
+            .parse::<usize>()
+            .map_err(|e| format_err!("bad JASC color count \"{}\": {}", count_line, e))?;
+
+        let colors = lines
+            .filter(|line| !line.is_empty())
+            .take(count)
+            .map(parse_rgb)
+            .collect::<Result<Vec<Color>, Error>>()?;
+        if colors.len() != count {
+            return Err(format_err!(
+                "JASC palette has {} colors, expected {}",
+                colors.len(),
+                count
+            ));
+        }
+        Self::from_imported(colors)
+    }
+
+    // Tile Layer Pro palettes are written in their SNES format.
+    pub fn to_tpl(&self) -> Vec<u8> {
+        let mut out = TPL_MAGIC.to_vec();
+        out.push(TPL_SNES);
+        out.extend(self.to_bgr555());
+        out
+    }
+
+    pub fn from_tpl(data: &[u8]) -> Result<Palette, Error> {
+        if data.len() < 4 || &data[..3] != TPL_MAGIC {
+            return Err(format_err!("not a Tile Layer Pro palette"));
+        }
+        match data[3] {
+            TPL_RGB => Self::from_imported(
+                data[4..]
+                    .chunks_exact(3)
+                    .map(|c| Color {
+                        r: c[0],
+                        g: c[1],
+                        b: c[2],
+                    })
+                    .collect(),
+            ),
+            TPL_SNES => Self::from_bgr555(&data[4..]),
+            format => Err(format_err!("unsupported Tile Layer Pro format {}", format)),
+        }
+    }
+
+    pub fn to_bgr555(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.colors.len() * 2);
+        for c in &self.colors {
+            out.write_u16::<LittleEndian>(c.to_bgr555()).unwrap();
+        }
+        out
+    }
+
+    pub fn from_bgr555(data: &[u8]) -> Result<Palette, Error> {
+        if !data.len().is_multiple_of(2) {
+            return Err(format_err!("odd sized BGR555 palette"));
+        }
+        let mut r = Cursor::new(data);
+        let mut colors = Vec::with_capacity(data.len() / 2);
+        for _ in 0..(data.len() / 2) {
+            colors.push(Color::from_bgr555(r.read_u16::<LittleEndian>()?));
+        }
+        Self::from_imported(colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_palette() -> Palette {
+        Palette::from_colors(
+            (0..32u16)
+                .map(|i| Color::from_bgr555(i | (31 - i) << 5 | (i / 2) << 10))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let palette = test_palette();
+        for format in &[
+            PaletteFormat::Gpl,
+            PaletteFormat::Jasc,
+            PaletteFormat::Tpl,
+            PaletteFormat::Bgr555,
+        ] {
+            let data = palette.export(*format, "test");
+            let imported = Palette::import(*format, &data).unwrap();
+            assert_eq!(imported.colors(), palette.colors(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn imports_are_quantised() {
+        let text = "GIMP Palette\nName: test\n#\n255 7 128\tlight\n  8  9 10\n";
+        let palette = Palette::from_gpl(text).unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[
+                Color {
+                    r: 0xf8,
+                    g: 0x08,
+                    b: 0x80
+                },
+                Color {
+                    r: 0x08,
+                    g: 0x08,
+                    b: 0x08
+                },
+            ]
+        );
+        assert_eq!(palette.colors()[0].to_bgr555(), 0x403f);
+        assert_eq!(Color { r: 3, g: 4, b: 0 }.to_bgr555(), 0x0020);
+
+        assert!(Palette::from_jasc("JASC-PAL\r\n0100\r\n2\r\n1 2 3\r\n").is_err());
+        assert!(Palette::from_tpl(b"TPL\x01").is_err());
+    }
+}
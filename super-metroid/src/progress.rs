@@ -0,0 +1,501 @@
+use failure::{format_err, Error};
+use num::FromPrimitive;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use super::save::SaveSlot;
+use super::start::StartingState;
+use super::{Area, Event, PlmItemId, RoomMdb, StateCondition, SuperMetroidData};
+
+// Energy without any tanks.
+pub const BASE_ENERGY: u16 = 99;
+
+// Bits of the per area boss flags.
+pub const BOSS_MAIN: u8 = 0x1;
+pub const BOSS_MINI: u8 = 0x2;
+pub const BOSS_TORIZO: u8 = 0x4;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub enum Item {
+    ETank,
+    Missile,
+    SuperMissile,
+    PowerBomb,
+    Bomb,
+    Charge,
+    Ice,
+    HiJump,
+    SpeedBooster,
+    Wave,
+    Spazer,
+    SpringBall,
+    Varia,
+    Gravity,
+    XRayScope,
+    Plasma,
+    Grapple,
+    SpaceJump,
+    ScrewAttack,
+    Morph,
+    Reserve,
+}
+
+// Which of Samus' two equipment words an item's bit lives in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EquipmentBits {
+    // Collected items at $7e:09a4, equipped at $7e:09a2.
+    Items(u16),
+    // Collected beams at $7e:09a8, equipped at $7e:09a6.
+    Beams(u16),
+}
+
+impl Item {
+    pub const ALL: [Item; 21] = [
+        Item::ETank,
+        Item::Missile,
+        Item::SuperMissile,
+        Item::PowerBomb,
+        Item::Bomb,
+        Item::Charge,
+        Item::Ice,
+        Item::HiJump,
+        Item::SpeedBooster,
+        Item::Wave,
+        Item::Spazer,
+        Item::SpringBall,
+        Item::Varia,
+        Item::Gravity,
+        Item::XRayScope,
+        Item::Plasma,
+        Item::Grapple,
+        Item::SpaceJump,
+        Item::ScrewAttack,
+        Item::Morph,
+        Item::Reserve,
+    ];
+
+    // The equipment bit for the item.  Tanks and ammo are counted instead.
+    pub fn equipment_bits(&self) -> Option<EquipmentBits> {
+        use EquipmentBits::*;
+        match self {
+            Item::Varia => Some(Items(0x0001)),
+            Item::SpringBall => Some(Items(0x0002)),
+            Item::Morph => Some(Items(0x0004)),
+            Item::ScrewAttack => Some(Items(0x0008)),
+            Item::Gravity => Some(Items(0x0020)),
+            Item::HiJump => Some(Items(0x0100)),
+            Item::SpaceJump => Some(Items(0x0200)),
+            Item::Bomb => Some(Items(0x1000)),
+            Item::SpeedBooster => Some(Items(0x2000)),
+            Item::Grapple => Some(Items(0x4000)),
+            Item::XRayScope => Some(Items(0x8000)),
+            Item::Wave => Some(Beams(0x0001)),
+            Item::Ice => Some(Beams(0x0002)),
+            Item::Spazer => Some(Beams(0x0004)),
+            Item::Plasma => Some(Beams(0x0008)),
+            Item::Charge => Some(Beams(0x1000)),
+            _ => None,
+        }
+    }
+}
+
+// Samus' equipment words and ammo maxima.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Equipment {
+    pub items: u16,
+    pub equipped_items: u16,
+    pub beams: u16,
+    pub equipped_beams: u16,
+    pub max_energy: u16,
+    pub max_missiles: u16,
+    pub max_super_missiles: u16,
+    pub max_power_bombs: u16,
+    pub max_reserve_energy: u16,
+}
+
+impl Equipment {
+    // Items held.  Tanks and ammo count once their maximum is above zero.
+    pub fn item_set(&self) -> HashSet<Item> {
+        Item::ALL
+            .iter()
+            .filter(|item| match item.equipment_bits() {
+                Some(EquipmentBits::Items(bit)) => self.items & bit != 0,
+                Some(EquipmentBits::Beams(bit)) => self.beams & bit != 0,
+                None => match item {
+                    Item::ETank => self.max_energy > BASE_ENERGY,
+                    Item::Missile => self.max_missiles > 0,
+                    Item::SuperMissile => self.max_super_missiles > 0,
+                    Item::PowerBomb => self.max_power_bombs > 0,
+                    Item::Reserve => self.max_reserve_energy > 0,
+                    _ => false,
+                },
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Gives or takes away an item that has an equipment bit.  Items are
+    // equipped when given.
+    pub fn set_item(&mut self, item: Item, has: bool) -> Result<(), Error> {
+        let (collected, equipped, bit) = match item.equipment_bits() {
+            Some(EquipmentBits::Items(bit)) => (&mut self.items, &mut self.equipped_items, bit),
+            Some(EquipmentBits::Beams(bit)) => (&mut self.beams, &mut self.equipped_beams, bit),
+            None => return Err(format_err!("{:?} is counted, not a bit", item)),
+        };
+        if has {
+            *collected |= bit;
+            *equipped |= bit;
+        } else {
+            *collected &= !bit;
+            *equipped &= !bit;
+        }
+        Ok(())
+    }
+}
+
+// Save slots and the new game state keep the equipment in fields named
+// like `Equipment`'s.
+macro_rules! equipment_from {
+    ($t:ty) => {
+        impl From<&$t> for Equipment {
+            fn from(s: &$t) -> Equipment {
+                Equipment {
+                    items: s.items,
+                    equipped_items: s.equipped_items,
+                    beams: s.beams,
+                    equipped_beams: s.equipped_beams,
+                    max_energy: s.max_energy,
+                    max_missiles: s.max_missiles,
+                    max_super_missiles: s.max_super_missiles,
+                    max_power_bombs: s.max_power_bombs,
+                    max_reserve_energy: s.max_reserve_energy,
+                }
+            }
+        }
+    };
+}
+
+equipment_from!(SaveSlot);
+equipment_from!(StartingState);
+
+impl PlmItemId {
+    // Returns the item given by the PLM regardless of how it is displayed.
+    pub fn item(&self) -> Item {
+        use PlmItemId::*;
+        match self {
+            ETank | ETankChozo | ETankHidden => Item::ETank,
+            Missile | MissileChozo | MissileHidden => Item::Missile,
+            SuperMissile | SuperMissileChozo | SuperMissileHidden => Item::SuperMissile,
+            PowerBomb | PowerBombChozo | PowerBombHidden => Item::PowerBomb,
+            Bomb | BombChozo | BombHidden => Item::Bomb,
+            Charge | ChargeChozo | ChargeHidden => Item::Charge,
+            Ice | IceChozo | IceHidden => Item::Ice,
+            HiJump | HiJumpChozo | HiJumpHidden => Item::HiJump,
+            SpeedBooster | SpeedBoosterChozo | SpeedBoosterHidden => Item::SpeedBooster,
+            Wave | WaveChozo | WaveHidden => Item::Wave,
+            Spazer | SpazerChozo | SpazerHidden => Item::Spazer,
+            SpringBall | SpringBallChozo | SpringBallHidden => Item::SpringBall,
+            Varia | VariaChozo | VariaHidden => Item::Varia,
+            Gravity | GravityChozo | GravityHidden => Item::Gravity,
+            XRayScope | XRayScopeChozo | XRayScopeHidden => Item::XRayScope,
+            Plasma | PlasmaChozo | PlasmaHidden => Item::Plasma,
+            Grapple | GrappleChozo | GrappleHidden => Item::Grapple,
+            SpaceJump | SpaceJumpChozo | SpaceJumpHidden => Item::SpaceJump,
+            ScrewAttack | ScrewAttackChozo | ScrewAttackHidden => Item::ScrewAttack,
+            Morph | MorphChozo | MorphHidden => Item::Morph,
+            Reserve | ReserveChozo | ReserveHidden => Item::Reserve,
+        }
+    }
+}
+
+// An item PLM placed in a room.  `param` is the item's bit in the collected
+// item bits at $7e:d870.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ItemLocation {
+    pub room_ptr: u16,
+    pub x: u8,
+    pub y: u8,
+    pub param: u16,
+    pub item: Item,
+}
+
+impl SuperMetroidData {
+    // Returns every item PLM in room discovery order.  An item that appears
+    // in several states of a room shares one collected bit and is listed
+    // once.
+    pub fn item_locations(&self) -> Vec<ItemLocation> {
+        let mut seen = HashSet::new();
+        let mut locations = Vec::new();
+        for room_ptr in &self.room_order {
+            let mdb = &self.room_mdb[room_ptr];
+            for state in &mdb.states {
+                let plms = match self.plm_population.get(&state.data.plm_ptr) {
+                    Some(plms) => plms,
+                    None => continue,
+                };
+                for plm in plms {
+                    let item = match PlmItemId::from_u16(plm.id) {
+                        Some(id) => id.item(),
+                        None => continue,
+                    };
+                    if seen.insert(plm.param) {
+                        locations.push(ItemLocation {
+                            room_ptr: *room_ptr,
+                            x: plm.x,
+                            y: plm.y,
+                            param: plm.param,
+                            item,
+                        });
+                    }
+                }
+            }
+        }
+        locations
+    }
+}
+
+// A point in a playthrough, as far as room states are concerned.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GameProgress {
+    pub events: HashSet<Event>,
+    // Boss flags (BOSS_*) for each area.
+    pub bosses: HashMap<Area, u8>,
+    // Items held.  Ammo counts as held once any tank of it is collected.
+    pub items: HashSet<Item>,
+    // Item locations that have been picked up.
+    pub collected: Vec<ItemLocation>,
+}
+
+impl GameProgress {
+    // Reads progress from a save slot.  `locations` are the game's item
+    // locations, as returned by `SuperMetroidData::item_locations`.
+    pub fn from_save_slot(slot: &SaveSlot, locations: &[ItemLocation]) -> GameProgress {
+        GameProgress {
+            events: slot.events.clone(),
+            bosses: slot
+                .bosses
+                .iter()
+                .filter(|(_, flags)| **flags != 0)
+                .map(|(area, flags)| (*area, *flags))
+                .collect(),
+            items: Equipment::from(slot).item_set(),
+            collected: locations
+                .iter()
+                .filter(|location| slot.is_item_collected(location.param))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // Reads progress from a dump of WRAM bank $7e taken while playing.
+    pub fn from_wram(wram: &[u8], locations: &[ItemLocation]) -> Result<GameProgress, Error> {
+        Ok(Self::from_save_slot(&SaveSlot::from_wram(wram)?, locations))
+    }
+
+    pub fn has_item(&self, item: Item) -> bool {
+        self.items.contains(&item)
+    }
+
+    pub fn has_event(&self, event: Event) -> bool {
+        self.events.contains(&event)
+    }
+
+    pub fn boss_flags(&self, area: Area) -> u8 {
+        self.bosses.get(&area).cloned().unwrap_or(0)
+    }
+
+    pub fn set_boss_dead(&mut self, area: Area, bosses: u8) {
+        *self.bosses.entry(area).or_insert(0) |= bosses;
+    }
+}
+
+impl StateCondition {
+    // Evaluates the condition the same way the game's state header routines
+    // do for a room in `area`.  `entry_door_ptr` is the bank $83 pointer of
+    // the door Samus enters through, if any.
+    pub fn is_met(&self, area: Area, progress: &GameProgress, entry_door_ptr: Option<u16>) -> bool {
+        match self {
+            StateCondition::Default => true,
+            StateCondition::DoorPointerIs { value } => entry_door_ptr == Some(*value),
+            StateCondition::MainAreaBossDead => progress.boss_flags(area) & BOSS_MAIN != 0,
+            StateCondition::EventSet { event } => progress.has_event(*event),
+            StateCondition::AreaBossesDead { bosses } => progress.boss_flags(area) & bosses != 0,
+            StateCondition::HasMorphBall => progress.has_item(Item::Morph),
+            StateCondition::HasMorphBallAndMissiles => {
+                progress.has_item(Item::Morph) && progress.has_item(Item::Missile)
+            }
+            StateCondition::HasPowerBombs => progress.has_item(Item::PowerBomb),
+            StateCondition::HasSpeedBooster => progress.has_item(Item::SpeedBooster),
+        }
+    }
+}
+
+impl RoomMdb {
+    // Returns the index of the state the game would load.  Conditions are
+    // checked in order and the first one met wins.  The default state is
+    // last so it is used when nothing else matches.
+    pub fn state_for(&self, progress: &GameProgress, entry_door_ptr: Option<u16>) -> usize {
+        self.states
+            .iter()
+            .position(|state| state.condition.is_met(self.area, progress, entry_door_ptr))
+            .unwrap_or_else(|| self.default_state())
+    }
+
+    // Returns the states that are picked by entering through the door with
+    // the given bank $83 pointer, regardless of game progress.
+    pub fn states_entered_by(&self, door_ptr: u16) -> Vec<usize> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| {
+                state.condition == StateCondition::DoorPointerIs { value: door_ptr }
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::add_room;
+    use super::super::*;
+    use super::*;
+
+    fn add_state(mdb: &mut RoomMdb, condition: StateCondition) {
+        let default = mdb.states.pop().unwrap();
+        let data = StateData {
+            level_data: 0xc2_0000 + mdb.states.len() as u32,
+            ..default.data.clone()
+        };
+        mdb.states.push(State { condition, data });
+        mdb.states.push(default);
+    }
+
+    #[test]
+    fn first_matching_state_wins() {
+        let mut sm = SuperMetroidData::default();
+        let mdb = add_room(&mut sm, 0x91f8, 1, &[], vec![]);
+        mdb.area = Area::Brinstar;
+        add_state(
+            mdb,
+            StateCondition::EventSet {
+                event: Event::ZebesAwake,
+            },
+        );
+        add_state(mdb, StateCondition::AreaBossesDead { bosses: BOSS_MINI });
+        add_state(mdb, StateCondition::HasMorphBallAndMissiles);
+
+        let mut progress = GameProgress::default();
+        assert_eq!(mdb.state_for(&progress, None), 3);
+
+        progress.items.insert(Item::Morph);
+        assert_eq!(mdb.state_for(&progress, None), 3);
+        progress.items.insert(Item::Missile);
+        assert_eq!(mdb.state_for(&progress, None), 2);
+
+        // Bosses are checked for the room's area.
+        progress.set_boss_dead(Area::Crateria, BOSS_MINI);
+        assert_eq!(mdb.state_for(&progress, None), 2);
+        progress.set_boss_dead(Area::Brinstar, BOSS_MINI);
+        assert_eq!(mdb.state_for(&progress, None), 1);
+
+        progress.events.insert(Event::ZebesAwake);
+        assert_eq!(mdb.state_for(&progress, None), 0);
+    }
+
+    #[test]
+    fn door_pointer_is_checked_against_entry_door() {
+        let mut sm = SuperMetroidData::default();
+        let mdb = add_room(&mut sm, 0x91f8, 1, &[], vec![]);
+        add_state(mdb, StateCondition::DoorPointerIs { value: 0x8916 });
+        add_state(mdb, StateCondition::HasMorphBall);
+
+        let mut progress = GameProgress::default();
+        progress.items.insert(Item::Morph);
+        assert_eq!(mdb.state_for(&progress, None), 1);
+        assert_eq!(mdb.state_for(&progress, Some(0x8922)), 1);
+        assert_eq!(mdb.state_for(&progress, Some(0x8916)), 0);
+        assert_eq!(mdb.states_entered_by(0x8916), vec![0]);
+    }
+
+    #[test]
+    fn equipment_items_set_collected_and_equipped_bits() {
+        let mut equipment = Equipment::default();
+        equipment.set_item(Item::Morph, true).unwrap();
+        equipment.set_item(Item::Wave, true).unwrap();
+        assert_eq!(
+            (equipment.items, equipment.equipped_items),
+            (0x0004, 0x0004)
+        );
+        assert_eq!(
+            (equipment.beams, equipment.equipped_beams),
+            (0x0001, 0x0001)
+        );
+        assert_eq!(
+            equipment.item_set(),
+            [Item::Morph, Item::Wave].iter().cloned().collect()
+        );
+
+        equipment.set_item(Item::Morph, false).unwrap();
+        assert_eq!((equipment.items, equipment.equipped_items), (0, 0));
+        // Tanks and ammo are counted by their maxima.
+        assert!(equipment.set_item(Item::ETank, true).is_err());
+    }
+
+    #[test]
+    fn progress_from_wram_lists_collected_locations() {
+        let mut sm = SuperMetroidData::default();
+        let mdb = add_room(&mut sm, 0x91f8, 1, &[], vec![]);
+        mdb.states[0].data.plm_ptr = 0x8000;
+        // Both states share the PLM list.
+        add_state(mdb, StateCondition::HasMorphBall);
+        sm.plm_population.insert(
+            0x8000,
+            vec![
+                PlmPopulation {
+                    id: PlmItemId::MissileChozo as u16,
+                    x: 3,
+                    y: 4,
+                    param: 0x0001,
+                },
+                PlmPopulation {
+                    id: 0xb703,
+                    x: 0,
+                    y: 0,
+                    param: 0x0000,
+                },
+                PlmPopulation {
+                    id: PlmItemId::ETankHidden as u16,
+                    x: 5,
+                    y: 6,
+                    param: 0x0009,
+                },
+            ],
+        );
+        let locations = sm.item_locations();
+        assert_eq!(
+            locations
+                .iter()
+                .map(|l| (l.param, l.item))
+                .collect::<Vec<_>>(),
+            vec![(0x0001, Item::Missile), (0x0009, Item::ETank)]
+        );
+
+        let mut wram = vec![0; 0x10000];
+        wram[save::WRAM_SAMUS + 2] = 0x04;
+        wram[save::WRAM_SLOT + save::EVENTS] = 0x01;
+        wram[save::WRAM_SLOT + save::BOSSES + Area::Norfair as usize] = BOSS_MINI;
+        wram[save::WRAM_SLOT + save::ITEM_BITS + 1] = 0x02;
+        let progress = GameProgress::from_wram(&wram, &locations).unwrap();
+        assert_eq!(progress.items, [Item::Morph].iter().cloned().collect());
+        assert!(progress.has_event(Event::ZebesAwake));
+        assert_eq!(progress.bosses.len(), 1);
+        assert_eq!(progress.boss_flags(Area::Norfair), BOSS_MINI);
+        assert_eq!(progress.collected, vec![locations[1].clone()]);
+        assert!(GameProgress::from_wram(&wram[..0xd000], &locations).is_err());
+
+        // The save buffer's explored maps are from the last save.
+        wram[save::WRAM_SLOT + save::MAP_EXPLORED] = 0xff;
+        assert!(SaveSlot::from_wram(&wram).unwrap().map_explored.is_empty());
+    }
+}
@@ -27,12 +27,86 @@ macro_rules! rom_addr_to_snes {
 }
 
 pub const ROOM_MDB_START: usize = rom_addr!(0x8f, 0x91f8);
+pub const LEVEL_DATA_BANK_START: usize = 0xc2;
+pub const LEVEL_DATA_BANK_END: usize = 0xce;
 pub const TILESET_POINTER_TABLE: usize = rom_addr!(0x8f, 0xe7a7);
 pub const TILESET_POINTER_TABLE_COUNT: usize = 29;
 pub const TILESET_ENTRY_BANK: usize = 0x8f;
 pub const CRE_TILES: usize = rom_addr!(0xb9, 0x8000);
 pub const CRE_TILE_TABLE: usize = rom_addr!(0xb9, 0xa09d);
 
+// Pause screen area maps.  One 64x32 tilemap per area, Crateria through
+// Ceres.
+pub const AREA_MAP_TILEMAPS: usize = rom_addr!(0xb5, 0x8000);
+pub const AREA_MAP_TILEMAP_SIZE: usize = 0x1000;
+pub const AREA_MAP_COUNT: usize = 7;
+pub const MAP_STATION_REVEAL_TABLE: usize = rom_addr!(0x82, 0x9717);
+pub const MAP_STATION_REVEAL_BANK: usize = 0x82;
+pub const MAP_TILES: usize = rom_addr!(0xb6, 0x8000);
+pub const MAP_TILES_SIZE: usize = 0x4000;
+pub const MAP_PALETTE: usize = rom_addr!(0xb6, 0xf000);
+
+// Animated tiles objects (bank $87) selected by the bits of an FX entry's
+// animated tiles bitset.
+pub const FX_ANIMATED_TILES_TABLE: usize = rom_addr!(0x89, 0xac46);
+// Per tile set lists of palette FX objects (bank $8d) selected by the bits
+// of an FX entry's palette FX bitset.
+pub const PALETTE_FX_TABLE: usize = rom_addr!(0x89, 0xaa02);
+pub const PALETTE_FX_TABLE_BANK: usize = 0x89;
+
+// Save and load stations.  One pointer per area, including the debug area,
+// to a list of 14 byte entries in bank $80.  The lists are stored one after
+// the other in area order.
+pub const LOAD_STATION_TABLE: usize = rom_addr!(0x80, 0xc4b5);
+pub const LOAD_STATION_BANK: usize = 0x80;
+pub const LOAD_STATION_AREA_COUNT: usize = 8;
+// Upper bound on the length of the last list, which has no list after it to
+// mark its end.  Indices below $10 are save stations and elevators and the
+// debug start locations follow.  The longest vanilla list ends at index $16.
+pub const MAX_LOAD_STATIONS_PER_AREA: usize = 0x17;
+
+// Song sets, indexed by `StateData::music_data_index`.  Each entry is a long
+// pointer to SPC engine data.
+pub const SONG_SET_TABLE: usize = rom_addr!(0x8f, 0xe7e1);
+pub const SONG_SET_COUNT: usize = 0x19;
+
+// Message box definitions in bank $85.  Each entry is a box setup routine,
+// a draw routine and the message's tilemap.
+pub const MESSAGE_DEFINITIONS: usize = rom_addr!(0x85, 0x869b);
+pub const MESSAGE_BANK: usize = 0x85;
+pub const MESSAGE_COUNT: usize = 0x1c;
+
+// Projectile damage.  Each table holds pointers into bank $93 to projectile
+// definitions that start with a damage word.  The beam tables have one
+// entry per beam combination and the non-beam table is indexed by
+// projectile type.
+pub const PROJECTILE_BANK: usize = 0x93;
+pub const BEAM_DAMAGE_TABLE: usize = rom_addr!(0x93, 0x83c1);
+pub const CHARGED_BEAM_DAMAGE_TABLE: usize = rom_addr!(0x93, 0x83d9);
+pub const NON_BEAM_DAMAGE_TABLE: usize = rom_addr!(0x93, 0x83f1);
+
+// Samus physics constants in bank $90.  Speeds are stored as a table of
+// whole pixels followed by a table of subpixels, one entry each for air,
+// water and lava/acid.
+pub const SAMUS_JUMP_SPEED: usize = rom_addr!(0x90, 0x9e97);
+pub const SAMUS_HI_JUMP_SPEED: usize = rom_addr!(0x90, 0x9ea3);
+pub const SAMUS_RUN_SPEED: usize = rom_addr!(0x90, 0x9eaf);
+// Frames of running before the speed booster activates.
+pub const SAMUS_SPEED_BOOSTER_CHARGE: usize = rom_addr!(0x90, 0x9eb3);
+// Immediate operands that set Samus' invincibility timer after taking
+// damage from enemies, enemy projectiles and spikes.
+pub const SAMUS_I_FRAMES: [usize; 3] = [
+    rom_addr!(0xa0, 0xa4c1),
+    rom_addr!(0xa0, 0xa5a5),
+    rom_addr!(0x94, 0x8f4a),
+];
+
+// Sets up Samus' equipment, ammo and start location for a new game.
+pub const NEW_GAME_INIT: usize = rom_addr!(0x81, 0xb2cb);
+// Free space at the end of bank $81.  New game values the init code can't
+// be patched to use are stored by a hook written here.
+pub const NEW_GAME_HOOK: usize = rom_addr!(0x81, 0xef1a);
+
 pub const ENEMY_TABLE_BANK: u8 = 0xa0;
 pub const ENEMY_TABLE_START: usize = rom_addr!(ENEMY_TABLE_BANK, 0xcebf);
 
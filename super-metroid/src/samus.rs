@@ -0,0 +1,242 @@
+use failure::{format_err, Error};
+use serde::Serialize;
+
+use super::rommap;
+use super::util::{check_len, read_u16, RomWriter};
+
+pub const BEAM_COUNT: usize = 12;
+// Beam combinations in the order of the damage tables.
+pub const BEAM_NAMES: [&str; BEAM_COUNT] = [
+    "power",
+    "wave",
+    "ice",
+    "ice_wave",
+    "spazer",
+    "spazer_wave",
+    "spazer_ice",
+    "spazer_ice_wave",
+    "plasma",
+    "plasma_wave",
+    "plasma_ice",
+    "plasma_ice_wave",
+];
+
+// Indices of the non-beam damage table.
+const MISSILE: usize = 1;
+const SUPER_MISSILE: usize = 2;
+const POWER_BOMB: usize = 3;
+
+// Where Samus is, for physics constants that change underwater.  Without
+// the gravity suit Samus uses the water and lava speeds; with it she always
+// uses the air ones.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Environment {
+    Air = 0,
+    Water = 1,
+    Lava = 2,
+}
+
+pub const ENVIRONMENT_COUNT: usize = 3;
+
+// A speed in pixels and 1/65536ths of a pixel per frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Speed {
+    pub pixels: u16,
+    pub subpixels: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SamusConstants {
+    // Indexed like `BEAM_NAMES`.
+    pub beam_damage: Vec<u16>,
+    pub charged_beam_damage: Vec<u16>,
+    pub missile_damage: u16,
+    pub super_missile_damage: u16,
+    pub power_bomb_damage: u16,
+    // Invincibility frames after damage, one per `rommap::SAMUS_I_FRAMES`
+    // location.
+    pub i_frames: Vec<u16>,
+    // Initial jump speeds indexed by `Environment`.
+    pub jump_speed: Vec<Speed>,
+    pub hi_jump_speed: Vec<Speed>,
+    pub run_speed: Speed,
+    pub speed_booster_charge_frames: u16,
+}
+
+// Reads the speed table at `offset`.  Whole pixels come first, then
+// subpixels.
+fn read_speeds(rom: &[u8], offset: usize, count: usize) -> Result<Vec<Speed>, Error> {
+    (0..count)
+        .map(|i| {
+            Ok(Speed {
+                pixels: read_u16(rom, offset + i * 2)?,
+                subpixels: read_u16(rom, offset + (count + i) * 2)?,
+            })
+        })
+        .collect()
+}
+
+fn write_speeds(w: &mut RomWriter, offset: usize, speeds: &[Speed]) -> Result<(), Error> {
+    for (i, speed) in speeds.iter().enumerate() {
+        w.write_u16(offset + i * 2, speed.pixels)?;
+        w.write_u16(offset + (speeds.len() + i) * 2, speed.subpixels)?;
+    }
+    Ok(())
+}
+
+// ROM offset of the damage word of entry `index` of a projectile table.
+fn damage_offset(rom: &[u8], table: usize, index: usize) -> Result<usize, Error> {
+    let ptr = read_u16(rom, table + index * 2)?;
+    if ptr < 0x8000 {
+        return Err(format_err!(
+            "bad projectile pointer {:04x} at {:x}",
+            ptr,
+            table + index * 2
+        ));
+    }
+    Ok(crate::rom_addr!(rommap::PROJECTILE_BANK, ptr))
+}
+
+fn read_damage(rom: &[u8], table: usize, count: usize) -> Result<Vec<u16>, Error> {
+    (0..count)
+        .map(|i| read_u16(rom, damage_offset(rom, table, i)?))
+        .collect()
+}
+
+fn write_damage(w: &mut RomWriter, table: usize, damage: &[u16]) -> Result<(), Error> {
+    for (i, val) in damage.iter().enumerate() {
+        let offset = damage_offset(w.data(), table, i)?;
+        w.write_u16(offset, *val)?;
+    }
+    Ok(())
+}
+
+impl SamusConstants {
+    pub fn load(rom: &[u8]) -> Result<SamusConstants, Error> {
+        let non_beam = |index| {
+            read_u16(
+                rom,
+                damage_offset(rom, rommap::NON_BEAM_DAMAGE_TABLE, index)?,
+            )
+        };
+        Ok(SamusConstants {
+            beam_damage: read_damage(rom, rommap::BEAM_DAMAGE_TABLE, BEAM_COUNT)?,
+            charged_beam_damage: read_damage(rom, rommap::CHARGED_BEAM_DAMAGE_TABLE, BEAM_COUNT)?,
+            missile_damage: non_beam(MISSILE)?,
+            super_missile_damage: non_beam(SUPER_MISSILE)?,
+            power_bomb_damage: non_beam(POWER_BOMB)?,
+            i_frames: rommap::SAMUS_I_FRAMES
+                .iter()
+                .map(|offset| read_u16(rom, *offset))
+                .collect::<Result<Vec<u16>, Error>>()?,
+            jump_speed: read_speeds(rom, rommap::SAMUS_JUMP_SPEED, ENVIRONMENT_COUNT)?,
+            hi_jump_speed: read_speeds(rom, rommap::SAMUS_HI_JUMP_SPEED, ENVIRONMENT_COUNT)?,
+            run_speed: read_speeds(rom, rommap::SAMUS_RUN_SPEED, 1)?[0],
+            speed_booster_charge_frames: read_u16(rom, rommap::SAMUS_SPEED_BOOSTER_CHARGE)?,
+        })
+    }
+
+    // Writes the constants back to where `load` found them.  Damage values
+    // are written through the ROM's projectile pointers.
+    pub fn write(&self, w: &mut RomWriter) -> Result<(), Error> {
+        check_len("beam_damage", self.beam_damage.len(), BEAM_COUNT)?;
+        check_len(
+            "charged_beam_damage",
+            self.charged_beam_damage.len(),
+            BEAM_COUNT,
+        )?;
+        check_len(
+            "i_frames",
+            self.i_frames.len(),
+            rommap::SAMUS_I_FRAMES.len(),
+        )?;
+        check_len("jump_speed", self.jump_speed.len(), ENVIRONMENT_COUNT)?;
+        check_len("hi_jump_speed", self.hi_jump_speed.len(), ENVIRONMENT_COUNT)?;
+
+        write_damage(w, rommap::BEAM_DAMAGE_TABLE, &self.beam_damage)?;
+        write_damage(
+            w,
+            rommap::CHARGED_BEAM_DAMAGE_TABLE,
+            &self.charged_beam_damage,
+        )?;
+        for (index, val) in &[
+            (MISSILE, self.missile_damage),
+            (SUPER_MISSILE, self.super_missile_damage),
+            (POWER_BOMB, self.power_bomb_damage),
+        ] {
+            let offset = damage_offset(w.data(), rommap::NON_BEAM_DAMAGE_TABLE, *index)?;
+            w.write_u16(offset, *val)?;
+        }
+        for (offset, val) in rommap::SAMUS_I_FRAMES.iter().zip(&self.i_frames) {
+            w.write_u16(*offset, *val)?;
+        }
+        write_speeds(w, rommap::SAMUS_JUMP_SPEED, &self.jump_speed)?;
+        write_speeds(w, rommap::SAMUS_HI_JUMP_SPEED, &self.hi_jump_speed)?;
+        write_speeds(w, rommap::SAMUS_RUN_SPEED, &[self.run_speed])?;
+        w.write_u16(
+            rommap::SAMUS_SPEED_BOOSTER_CHARGE,
+            self.speed_booster_charge_frames,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x200000];
+        let mut w = RomWriter::new(&mut rom);
+        // Point every projectile table entry at its own definition.
+        let mut ptr = 0x9000;
+        for (table, count) in &[
+            (rommap::BEAM_DAMAGE_TABLE, BEAM_COUNT),
+            (rommap::CHARGED_BEAM_DAMAGE_TABLE, BEAM_COUNT),
+            (rommap::NON_BEAM_DAMAGE_TABLE, POWER_BOMB + 1),
+        ] {
+            for i in 0..*count {
+                w.write_u16(table + i * 2, ptr).unwrap();
+                w.write_u16(crate::rom_addr!(rommap::PROJECTILE_BANK, ptr), ptr)
+                    .unwrap();
+                ptr += 0x10;
+            }
+        }
+        w.write_u16(rommap::SAMUS_JUMP_SPEED, 4).unwrap();
+        w.write_u16(rommap::SAMUS_JUMP_SPEED + 6, 0xe000).unwrap();
+        w.write_u16(rommap::SAMUS_SPEED_BOOSTER_CHARGE, 0x82)
+            .unwrap();
+        rom
+    }
+
+    #[test]
+    fn constants_round_trip() {
+        let mut rom = test_rom();
+        let mut constants = SamusConstants::load(&rom).unwrap();
+        assert_eq!(constants.beam_damage[1], 0x9010);
+        assert_eq!(constants.charged_beam_damage[0], 0x90c0);
+        assert_eq!(constants.missile_damage, 0x9190);
+        assert_eq!(
+            constants.jump_speed[Environment::Air as usize],
+            Speed {
+                pixels: 4,
+                subpixels: 0xe000
+            }
+        );
+        assert_eq!(constants.speed_booster_charge_frames, 0x82);
+
+        constants.beam_damage[8] = 300;
+        constants.power_bomb_damage = 400;
+        constants.hi_jump_speed[Environment::Water as usize].pixels = 5;
+        constants.write(&mut RomWriter::new(&mut rom)).unwrap();
+        assert_eq!(SamusConstants::load(&rom).unwrap(), constants);
+
+        constants.i_frames.pop();
+        assert!(constants.write(&mut RomWriter::new(&mut rom)).is_err());
+
+        // A projectile pointer outside of the bank is an error.
+        RomWriter::new(&mut rom)
+            .write_u16(rommap::NON_BEAM_DAMAGE_TABLE + POWER_BOMB * 2, 0x0100)
+            .unwrap();
+        assert!(SamusConstants::load(&rom).is_err());
+    }
+}
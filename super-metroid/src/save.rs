@@ -0,0 +1,469 @@
+use failure::{format_err, Error};
+use num::FromPrimitive;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use super::map::{MapBits, MAP_BITS_SIZE, MAP_H, MAP_PAGE_W, MAP_W};
+use super::progress::{Equipment, Item};
+use super::util::{check_len, read_u16, RomWriter};
+use super::{Area, Event};
+
+// SRAM holds three save slots.  Each slot has a checksum and its complement
+// in the header at the start of SRAM and again in a mirror at the end.  The
+// game accepts a slot if either copy matches its contents.
+pub const SRAM_SIZE: usize = 0x2000;
+pub const SLOT_COUNT: usize = 3;
+pub const SLOT_SIZE: usize = 0x65c;
+const SLOTS_START: usize = 0x10;
+const CHECKSUMS: usize = 0x0000;
+const COMPLEMENTS: usize = 0x0008;
+const MIRROR_CHECKSUMS: usize = 0x1ff0;
+const MIRROR_COMPLEMENTS: usize = 0x1ff8;
+
+// Where a slot is saved from in WRAM bank $7e.  Samus' variables are copied
+// into the start of the slot when saving, so a live game has them at
+// `WRAM_SAMUS` instead.
+pub const WRAM_SLOT: usize = 0xd7c0;
+pub const WRAM_SAMUS: usize = 0x09a2;
+const SAMUS_SIZE: usize = 0x60;
+
+// Offsets within a slot.  A slot is a copy of WRAM starting at $7e:d7c0, the
+// start of which is filled from Samus' variables at $7e:09a2.
+const EQUIPPED_ITEMS: usize = 0x00;
+const ITEMS: usize = 0x02;
+const EQUIPPED_BEAMS: usize = 0x04;
+const BEAMS: usize = 0x06;
+const ENERGY: usize = 0x20;
+const MAX_ENERGY: usize = 0x22;
+const MISSILES: usize = 0x24;
+const MAX_MISSILES: usize = 0x26;
+const SUPER_MISSILES: usize = 0x28;
+const MAX_SUPER_MISSILES: usize = 0x2a;
+const POWER_BOMBS: usize = 0x2c;
+const MAX_POWER_BOMBS: usize = 0x2e;
+const MAX_RESERVE_ENERGY: usize = 0x32;
+const RESERVE_ENERGY: usize = 0x34;
+// $7e:d820, one bit per `Event`.
+pub const EVENTS: usize = 0x60;
+pub const EVENTS_SIZE: usize = 8;
+// $7e:d828, one byte of boss flags per area.
+pub const BOSSES: usize = 0x68;
+pub const BOSSES_SIZE: usize = 8;
+// $7e:d870, one bit per item PLM indexed by the PLM's param.
+pub const ITEM_BITS: usize = 0xb0;
+pub const ITEM_BITS_SIZE: usize = 0x40;
+// $7e:d8b0, one bit per door PLM indexed by the PLM's param.
+pub const DOOR_BITS: usize = 0xf0;
+pub const DOOR_BITS_SIZE: usize = 0x40;
+// $7e:d916 and $7e:d918.
+const LOAD_STATION: usize = 0x156;
+const AREA: usize = 0x158;
+// Explored map tiles of the areas in `MAP_EXPLORED_SIZES`, one after the
+// other.
+pub const MAP_EXPLORED: usize = 0x15c;
+// Bytes of each area's `MapBits` the save routine keeps.  Wrecked Ship and
+// Tourian fit in the left half of the map so only that half is saved.
+const MAP_EXPLORED_SIZES: [(Area, usize); 6] = [
+    (Area::Crateria, MAP_BITS_SIZE),
+    (Area::Brinstar, MAP_BITS_SIZE),
+    (Area::Norfair, MAP_BITS_SIZE),
+    (Area::WreckedShip, MAP_BITS_SIZE / 2),
+    (Area::Maridia, MAP_BITS_SIZE),
+    (Area::Tourian, MAP_BITS_SIZE / 2),
+];
+
+fn get_bit(bits: &[u8], index: usize) -> bool {
+    bits.get(index / 8)
+        .is_some_and(|b| b & (1 << (index % 8)) != 0)
+}
+
+fn set_bit(bits: &mut [u8], index: usize, set: bool) -> Result<(), Error> {
+    let len = bits.len();
+    let b = bits
+        .get_mut(index / 8)
+        .ok_or_else(|| format_err!("bit {} is outside of the {} byte table", index, len))?;
+    if set {
+        *b |= 1 << (index % 8);
+    } else {
+        *b &= !(1 << (index % 8));
+    }
+    Ok(())
+}
+
+// The game sums the slot as little endian words.
+pub fn checksum(slot: &[u8]) -> u16 {
+    slot.chunks(2).fold(0u16, |sum, word| {
+        let lo = word[0] as u16;
+        let hi = word.get(1).cloned().unwrap_or(0) as u16;
+        sum.wrapping_add(lo | hi << 8)
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SaveSlot {
+    pub items: u16,
+    pub equipped_items: u16,
+    pub beams: u16,
+    pub equipped_beams: u16,
+    pub energy: u16,
+    pub max_energy: u16,
+    pub missiles: u16,
+    pub max_missiles: u16,
+    pub super_missiles: u16,
+    pub max_super_missiles: u16,
+    pub power_bombs: u16,
+    pub max_power_bombs: u16,
+    pub reserve_energy: u16,
+    pub max_reserve_energy: u16,
+    pub events: HashSet<Event>,
+    // Boss flags (progress::BOSS_*) for each area.
+    pub bosses: HashMap<Area, u8>,
+    pub item_bits: Vec<u8>,
+    pub door_bits: Vec<u8>,
+    // Explored map tiles of the areas that have them saved.
+    pub map_explored: HashMap<Area, MapBits>,
+    // Index of the area and the save station within it.
+    pub area: u16,
+    pub load_station: u16,
+
+    // The slot as loaded.  Parts without a field are written back as is.
+    #[serde(skip)]
+    data: Vec<u8>,
+}
+
+impl SaveSlot {
+    pub fn from_bytes(data: &[u8]) -> Result<SaveSlot, Error> {
+        if data.len() < SLOT_SIZE {
+            return Err(format_err!("save slot too short: {} bytes", data.len()));
+        }
+        let data = &data[..SLOT_SIZE];
+        let events = (0..EVENTS_SIZE * 8)
+            .filter(|i| get_bit(&data[EVENTS..EVENTS + EVENTS_SIZE], *i))
+            .filter_map(Event::from_usize)
+            .collect();
+        let bosses = (0..BOSSES_SIZE)
+            .filter_map(|i| Some((Area::from_usize(i)?, data[BOSSES + i])))
+            .collect();
+        let mut map_explored = HashMap::new();
+        let mut offset = MAP_EXPLORED;
+        for (area, size) in &MAP_EXPLORED_SIZES {
+            let mut bits = MapBits::new();
+            bits.data[..*size].copy_from_slice(&data[offset..offset + size]);
+            map_explored.insert(*area, bits);
+            offset += size;
+        }
+        Ok(SaveSlot {
+            items: read_u16(data, ITEMS)?,
+            equipped_items: read_u16(data, EQUIPPED_ITEMS)?,
+            beams: read_u16(data, BEAMS)?,
+            equipped_beams: read_u16(data, EQUIPPED_BEAMS)?,
+            energy: read_u16(data, ENERGY)?,
+            max_energy: read_u16(data, MAX_ENERGY)?,
+            missiles: read_u16(data, MISSILES)?,
+            max_missiles: read_u16(data, MAX_MISSILES)?,
+            super_missiles: read_u16(data, SUPER_MISSILES)?,
+            max_super_missiles: read_u16(data, MAX_SUPER_MISSILES)?,
+            power_bombs: read_u16(data, POWER_BOMBS)?,
+            max_power_bombs: read_u16(data, MAX_POWER_BOMBS)?,
+            reserve_energy: read_u16(data, RESERVE_ENERGY)?,
+            max_reserve_energy: read_u16(data, MAX_RESERVE_ENERGY)?,
+            events,
+            bosses,
+            item_bits: data[ITEM_BITS..ITEM_BITS + ITEM_BITS_SIZE].to_vec(),
+            door_bits: data[DOOR_BITS..DOOR_BITS + DOOR_BITS_SIZE].to_vec(),
+            map_explored,
+            area: read_u16(data, AREA)?,
+            load_station: read_u16(data, LOAD_STATION)?,
+            data: data.to_vec(),
+        })
+    }
+
+    // Builds a slot from a dump of WRAM bank $7e, the way the game would
+    // save it. The explored maps are left empty: the game only copies them
+    // into the save buffer when saving, so the buffer holds the last save's.
+    pub fn from_wram(wram: &[u8]) -> Result<SaveSlot, Error> {
+        let slot = wram
+            .get(WRAM_SLOT..WRAM_SLOT + SLOT_SIZE)
+            .ok_or_else(|| format_err!("WRAM dump too short: {} bytes", wram.len()))?;
+        let mut data = slot.to_vec();
+        data[..SAMUS_SIZE].copy_from_slice(&wram[WRAM_SAMUS..WRAM_SAMUS + SAMUS_SIZE]);
+        let mut slot = SaveSlot::from_bytes(&data)?;
+        slot.map_explored.clear();
+        Ok(slot)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        check_len("item_bits", self.item_bits.len(), ITEM_BITS_SIZE)?;
+        check_len("door_bits", self.door_bits.len(), DOOR_BITS_SIZE)?;
+
+        let mut data = self.data.clone();
+        data.resize(SLOT_SIZE, 0);
+        for (offset, val) in &[
+            (ITEMS, self.items),
+            (EQUIPPED_ITEMS, self.equipped_items),
+            (BEAMS, self.beams),
+            (EQUIPPED_BEAMS, self.equipped_beams),
+            (ENERGY, self.energy),
+            (MAX_ENERGY, self.max_energy),
+            (MISSILES, self.missiles),
+            (MAX_MISSILES, self.max_missiles),
+            (SUPER_MISSILES, self.super_missiles),
+            (MAX_SUPER_MISSILES, self.max_super_missiles),
+            (POWER_BOMBS, self.power_bombs),
+            (MAX_POWER_BOMBS, self.max_power_bombs),
+            (RESERVE_ENERGY, self.reserve_energy),
+            (MAX_RESERVE_ENERGY, self.max_reserve_energy),
+            (AREA, self.area),
+            (LOAD_STATION, self.load_station),
+        ] {
+            RomWriter::new(&mut data).write_u16(*offset, *val)?;
+        }
+        // Only bits with an `Event` are touched so unknown ones survive.
+        for i in 0..EVENTS_SIZE * 8 {
+            if let Some(event) = Event::from_usize(i) {
+                set_bit(
+                    &mut data[EVENTS..EVENTS + EVENTS_SIZE],
+                    i,
+                    self.events.contains(&event),
+                )?;
+            }
+        }
+        for (area, flags) in &self.bosses {
+            data[BOSSES + *area as usize] = *flags;
+        }
+        data[ITEM_BITS..ITEM_BITS + ITEM_BITS_SIZE].copy_from_slice(&self.item_bits);
+        data[DOOR_BITS..DOOR_BITS + DOOR_BITS_SIZE].copy_from_slice(&self.door_bits);
+        let mut offset = MAP_EXPLORED;
+        for (area, size) in &MAP_EXPLORED_SIZES {
+            if let Some(bits) = self.map_explored.get(area) {
+                check_len("map_explored", bits.data.len(), MAP_BITS_SIZE)?;
+                data[offset..offset + size].copy_from_slice(&bits.data[..*size]);
+            } else {
+                data[offset..offset + size].iter_mut().for_each(|b| *b = 0);
+            }
+            offset += size;
+        }
+        Ok(data)
+    }
+
+    // True if the item PLM with `param` has been collected.
+    pub fn is_item_collected(&self, param: u16) -> bool {
+        get_bit(&self.item_bits, param as usize)
+    }
+
+    pub fn set_item_collected(&mut self, param: u16, collected: bool) -> Result<(), Error> {
+        set_bit(&mut self.item_bits, param as usize, collected)
+    }
+
+    // True if the door PLM with `param` has been opened.
+    pub fn is_door_opened(&self, param: u16) -> bool {
+        get_bit(&self.door_bits, param as usize)
+    }
+
+    pub fn set_door_opened(&mut self, param: u16, opened: bool) -> Result<(), Error> {
+        set_bit(&mut self.door_bits, param as usize, opened)
+    }
+
+    // True if map tile (`x`, `y`) of `area` has been explored.
+    pub fn is_map_tile_explored(&self, area: Area, x: usize, y: usize) -> bool {
+        self.map_explored
+            .get(&area)
+            .is_some_and(|bits| bits.get(x, y))
+    }
+
+    // Fails for areas and tiles the save routine doesn't keep.
+    pub fn set_map_tile_explored(
+        &mut self,
+        area: Area,
+        x: usize,
+        y: usize,
+        explored: bool,
+    ) -> Result<(), Error> {
+        let size = MAP_EXPLORED_SIZES
+            .iter()
+            .find(|(a, _)| *a == area)
+            .map(|(_, size)| *size)
+            .ok_or_else(|| format_err!("{:?} has no saved map", area))?;
+        let width = if size == MAP_BITS_SIZE {
+            MAP_W
+        } else {
+            MAP_PAGE_W
+        };
+        if x >= width || y >= MAP_H {
+            return Err(format_err!(
+                "map tile ({}, {}) of {:?} is not saved",
+                x,
+                y,
+                area
+            ));
+        }
+        self.map_explored
+            .entry(area)
+            .or_default()
+            .set(x, y, explored);
+        Ok(())
+    }
+
+    // See `Equipment::set_item`.
+    pub fn set_item(&mut self, item: Item, has: bool) -> Result<(), Error> {
+        let mut equipment = Equipment::from(&*self);
+        equipment.set_item(item, has)?;
+        self.items = equipment.items;
+        self.equipped_items = equipment.equipped_items;
+        self.beams = equipment.beams;
+        self.equipped_beams = equipment.equipped_beams;
+        Ok(())
+    }
+}
+
+fn slot_offset(slot: usize) -> usize {
+    SLOTS_START + slot * SLOT_SIZE
+}
+
+// A whole SRAM file.  Empty and corrupt slots are None.
+#[derive(Clone, Debug, Serialize)]
+pub struct SaveFile {
+    pub slots: Vec<Option<SaveSlot>>,
+
+    #[serde(skip)]
+    data: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn from_bytes(data: &[u8]) -> Result<SaveFile, Error> {
+        if data.len() < SRAM_SIZE {
+            return Err(format_err!("SRAM too short: {} bytes", data.len()));
+        }
+        let slots = (0..SLOT_COUNT)
+            .map(|slot| {
+                let offset = slot_offset(slot);
+                let contents = &data[offset..offset + SLOT_SIZE];
+                let sum = checksum(contents);
+                let valid = |checksums: usize, complements: usize| -> Result<bool, Error> {
+                    Ok(read_u16(data, checksums + slot * 2)? == sum
+                        && read_u16(data, complements + slot * 2)? == !sum)
+                };
+                if valid(CHECKSUMS, COMPLEMENTS)? || valid(MIRROR_CHECKSUMS, MIRROR_COMPLEMENTS)? {
+                    SaveSlot::from_bytes(contents).map(Some)
+                } else {
+                    Ok(None)
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(SaveFile {
+            slots,
+            data: data[..SRAM_SIZE].to_vec(),
+        })
+    }
+
+    // Returns the SRAM with every slot that is present written and
+    // checksummed in both copies.  Empty slots keep their old contents.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if self.slots.len() != SLOT_COUNT {
+            return Err(format_err!(
+                "{} save slots, expected {}",
+                self.slots.len(),
+                SLOT_COUNT
+            ));
+        }
+        let mut data = self.data.clone();
+        for (i, slot) in self.slots.iter().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot.to_bytes()?,
+                None => continue,
+            };
+            let offset = slot_offset(i);
+            data[offset..offset + SLOT_SIZE].copy_from_slice(&slot);
+            let sum = checksum(&slot);
+            let mut w = RomWriter::new(&mut data);
+            w.write_u16(CHECKSUMS + i * 2, sum)?;
+            w.write_u16(COMPLEMENTS + i * 2, !sum)?;
+            w.write_u16(MIRROR_CHECKSUMS + i * 2, sum)?;
+            w.write_u16(MIRROR_COMPLEMENTS + i * 2, !sum)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::BOSS_MAIN;
+
+    fn test_sram() -> Vec<u8> {
+        let mut data = vec![0; SRAM_SIZE];
+        let mut slot = vec![0; SLOT_SIZE];
+        let mut w = RomWriter::new(&mut slot);
+        w.write_u16(MAX_ENERGY, 299).unwrap();
+        w.write_u16(MAX_MISSILES, 10).unwrap();
+        slot[EVENTS] = 0x01;
+        // An event bit the enum doesn't know about.
+        slot[EVENTS + 3] = 0x80;
+        slot[BOSSES + Area::Brinstar as usize] = BOSS_MAIN;
+        slot[ITEM_BITS] = 0x02;
+        // Norfair comes after Crateria and Brinstar.  Its right half starts
+        // halfway through its map.
+        slot[MAP_EXPLORED + 2 * MAP_BITS_SIZE + MAP_BITS_SIZE / 2 + 8] = 0x40;
+        let sum = checksum(&slot);
+        let offset = slot_offset(1);
+        data[offset..offset + SLOT_SIZE].copy_from_slice(&slot);
+        // Only the mirror is valid.
+        let mut w = RomWriter::new(&mut data);
+        w.write_u16(MIRROR_CHECKSUMS + 2, sum).unwrap();
+        w.write_u16(MIRROR_COMPLEMENTS + 2, !sum).unwrap();
+        data
+    }
+
+    #[test]
+    fn slots_load_edit_and_write() {
+        let sram = test_sram();
+        let mut save = SaveFile::from_bytes(&sram).unwrap();
+        assert!(save.slots[0].is_none());
+        assert!(save.slots[2].is_none());
+
+        let slot = save.slots[1].as_mut().unwrap();
+        assert_eq!(slot.max_energy, 299);
+        assert_eq!(slot.max_missiles, 10);
+        assert_eq!(slot.events, [Event::ZebesAwake].iter().cloned().collect());
+        assert_eq!(slot.bosses[&Area::Brinstar], BOSS_MAIN);
+        assert!(slot.is_item_collected(1));
+        assert!(!slot.is_item_collected(0));
+
+        assert!(slot.is_map_tile_explored(Area::Norfair, 33, 2));
+        assert!(!slot.is_map_tile_explored(Area::Norfair, 32, 2));
+
+        slot.set_item(Item::Varia, true).unwrap();
+        slot.set_door_opened(0x1f, true).unwrap();
+        slot.set_map_tile_explored(Area::Tourian, 31, 31, true)
+            .unwrap();
+        assert!(slot
+            .set_map_tile_explored(Area::Tourian, 32, 0, true)
+            .is_err());
+        assert!(slot.set_map_tile_explored(Area::Ceres, 0, 0, true).is_err());
+        slot.events.insert(Event::TourianUnlocked);
+        assert!(slot.set_item(Item::Missile, true).is_err());
+        assert!(slot.set_item_collected(0x200, true).is_err());
+
+        let written = save.to_bytes().unwrap();
+        let reloaded = SaveFile::from_bytes(&written).unwrap();
+        let slot = reloaded.slots[1].as_ref().unwrap();
+        assert_eq!(
+            slot.to_bytes().unwrap(),
+            save.slots[1].as_ref().unwrap().to_bytes().unwrap()
+        );
+        assert_eq!(slot.equipped_items, 0x0001);
+        assert!(slot.is_door_opened(0x1f));
+        assert!(slot.is_map_tile_explored(Area::Tourian, 31, 31));
+        assert_eq!(slot.to_bytes().unwrap()[SLOT_SIZE - 1], 0x01);
+        assert_eq!(slot.to_bytes().unwrap()[EVENTS + 3], 0x80);
+        // Both checksum copies are written.
+        assert_eq!(
+            read_u16(&written, CHECKSUMS + 2).unwrap(),
+            checksum(&slot.data)
+        );
+        assert_eq!(
+            read_u16(&written, MIRROR_COMPLEMENTS + 2).unwrap(),
+            !checksum(&slot.data)
+        );
+    }
+}
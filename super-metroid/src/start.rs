@@ -0,0 +1,492 @@
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use super::disasm::{self, Flags, Mode, SnesAddr};
+use super::progress::{Equipment, Item};
+use super::rommap;
+use super::stations::LoadStation;
+use super::util::RomWriter;
+
+// WRAM addresses of the values the new game code sets.
+const EQUIPPED_ITEMS: u16 = 0x09a2;
+const ITEMS: u16 = 0x09a4;
+const EQUIPPED_BEAMS: u16 = 0x09a6;
+const BEAMS: u16 = 0x09a8;
+const ENERGY: u16 = 0x09c2;
+const MAX_ENERGY: u16 = 0x09c4;
+const MISSILES: u16 = 0x09c6;
+const MAX_MISSILES: u16 = 0x09c8;
+const SUPER_MISSILES: u16 = 0x09ca;
+const MAX_SUPER_MISSILES: u16 = 0x09cc;
+const POWER_BOMBS: u16 = 0x09ce;
+const MAX_POWER_BOMBS: u16 = 0x09d0;
+const MAX_RESERVE_ENERGY: u16 = 0x09d4;
+const RESERVE_ENERGY: u16 = 0x09d6;
+const LOAD_STATION: u16 = 0x078b;
+const AREA: u16 = 0x079f;
+
+// A value stored by the new game code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Store {
+    value: u16,
+    // ROM offset and size of the `LDA #imm` operand the value came from.
+    // None for STZ.
+    operand: Option<(usize, usize)>,
+}
+
+// The values a new game starts with.  Loading finds them by disassembling
+// the new game code and the routines it calls for immediate loads stored to
+// Samus' WRAM variables, so ROMs that move or rewrite that code still work
+// as long as they set the values the same way.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StartingState {
+    pub items: u16,
+    pub equipped_items: u16,
+    pub beams: u16,
+    pub equipped_beams: u16,
+    pub energy: u16,
+    pub max_energy: u16,
+    pub missiles: u16,
+    pub max_missiles: u16,
+    pub super_missiles: u16,
+    pub max_super_missiles: u16,
+    pub power_bombs: u16,
+    pub max_power_bombs: u16,
+    pub reserve_energy: u16,
+    pub max_reserve_energy: u16,
+    // Index of the area and the save or load station within it.
+    pub area: u16,
+    pub load_station: u16,
+
+    #[serde(skip)]
+    stores: HashMap<u16, Store>,
+}
+
+// True for instructions that leave the accumulator alone.
+fn keeps_a(mnemonic: &str) -> bool {
+    matches!(
+        mnemonic,
+        "STA"
+            | "STX"
+            | "STY"
+            | "STZ"
+            | "LDX"
+            | "LDY"
+            | "INX"
+            | "INY"
+            | "DEX"
+            | "DEY"
+            | "TAX"
+            | "TAY"
+            | "PHA"
+            | "PHX"
+            | "PHY"
+            | "PHP"
+            | "PHB"
+            | "PLB"
+            | "REP"
+            | "SEP"
+            | "CLC"
+            | "SEC"
+            | "NOP"
+    )
+}
+
+// WRAM address written by a store instruction.
+fn store_addr(inst: &disasm::Instruction) -> Option<u16> {
+    let operand = inst.operand();
+    let addr = match inst.mode {
+        // Data bank $80-$bf and $00-$3f mirror the start of WRAM.
+        Mode::Absolute if operand < 0x2000 => operand as u16,
+        Mode::AbsoluteLong if operand >> 16 == 0x7e => operand as u16,
+        _ => return None,
+    };
+    Some(addr)
+}
+
+// Records the stores the routine at `entry` makes.  Calls are scanned
+// where they happen, `depth` levels deep.  Jumps, like the one to the hook
+// `StartingState::write` adds, continue the routine at the same depth and
+// are followed once each.
+fn scan(
+    rom: &[u8],
+    entry: SnesAddr,
+    flags: Flags,
+    depth: usize,
+    stores: &mut HashMap<u16, Store>,
+    jumps: &mut HashSet<SnesAddr>,
+) -> Result<(), Error> {
+    let listing = disasm::disassemble(rom, entry, flags, None)?;
+    let mut a: Option<Store> = None;
+    for inst in &listing.instructions {
+        // Other paths can reach a label with anything in A.
+        if inst.addr != entry && listing.labels.contains_key(&inst.addr) {
+            a = None;
+        }
+        match (inst.mnemonic, inst.mode) {
+            ("LDA", Mode::ImmediateM) => {
+                let operand = inst
+                    .addr
+                    .wrapping_add(1)
+                    .to_rom_offset()
+                    .map(|offset| (offset, inst.bytes.len() - 1));
+                a = Some(Store {
+                    value: inst.operand() as u16,
+                    operand,
+                });
+            }
+            ("STA", _) => {
+                if let (Some(addr), Some(store)) = (store_addr(inst), a) {
+                    stores.insert(addr, store);
+                }
+            }
+            ("STZ", _) => {
+                if let Some(addr) = store_addr(inst) {
+                    stores.insert(
+                        addr,
+                        Store {
+                            value: 0,
+                            operand: None,
+                        },
+                    );
+                }
+            }
+            ("JSR", _) | ("JSL", _) => {
+                if let (Some(target), true) = (inst.target(), depth > 0) {
+                    // Shared routines that can't be followed don't set up
+                    // the new game.
+                    let _ = scan(rom, target, Flags::default(), depth - 1, stores, jumps);
+                }
+                a = None;
+            }
+            ("JMP", Mode::Absolute) | ("JML", Mode::AbsoluteLong) => {
+                if let Some(target) = inst.target() {
+                    if jumps.insert(target) {
+                        scan(rom, target, inst.flags, depth, stores, jumps)?;
+                    }
+                }
+            }
+            (mnemonic, _) if keeps_a(mnemonic) => (),
+            _ => a = None,
+        }
+    }
+    Ok(())
+}
+
+impl StartingState {
+    pub fn load(rom: &[u8]) -> Result<StartingState, Error> {
+        let entry = SnesAddr(crate::rom_addr_to_snes!(rommap::NEW_GAME_INIT));
+        let mut stores = HashMap::new();
+        scan(
+            rom,
+            entry,
+            Flags::default(),
+            1,
+            &mut stores,
+            &mut HashSet::new(),
+        )?;
+
+        let mut state = StartingState::default();
+        for (addr, store) in &stores {
+            if let Some(field) = state.field_mut(*addr) {
+                *field = store.value;
+            }
+        }
+        state.stores = stores;
+        Ok(state)
+    }
+
+    fn field_mut(&mut self, addr: u16) -> Option<&mut u16> {
+        Some(match addr {
+            EQUIPPED_ITEMS => &mut self.equipped_items,
+            ITEMS => &mut self.items,
+            EQUIPPED_BEAMS => &mut self.equipped_beams,
+            BEAMS => &mut self.beams,
+            ENERGY => &mut self.energy,
+            MAX_ENERGY => &mut self.max_energy,
+            MISSILES => &mut self.missiles,
+            MAX_MISSILES => &mut self.max_missiles,
+            SUPER_MISSILES => &mut self.super_missiles,
+            MAX_SUPER_MISSILES => &mut self.max_super_missiles,
+            POWER_BOMBS => &mut self.power_bombs,
+            MAX_POWER_BOMBS => &mut self.max_power_bombs,
+            RESERVE_ENERGY => &mut self.reserve_energy,
+            MAX_RESERVE_ENERGY => &mut self.max_reserve_energy,
+            LOAD_STATION => &mut self.load_station,
+            AREA => &mut self.area,
+            _ => return None,
+        })
+    }
+
+    fn fields(&self) -> [(u16, u16); 16] {
+        [
+            (EQUIPPED_ITEMS, self.equipped_items),
+            (ITEMS, self.items),
+            (EQUIPPED_BEAMS, self.equipped_beams),
+            (BEAMS, self.beams),
+            (ENERGY, self.energy),
+            (MAX_ENERGY, self.max_energy),
+            (MISSILES, self.missiles),
+            (MAX_MISSILES, self.max_missiles),
+            (SUPER_MISSILES, self.super_missiles),
+            (MAX_SUPER_MISSILES, self.max_super_missiles),
+            (POWER_BOMBS, self.power_bombs),
+            (MAX_POWER_BOMBS, self.max_power_bombs),
+            (RESERVE_ENERGY, self.reserve_energy),
+            (MAX_RESERVE_ENERGY, self.max_reserve_energy),
+            (LOAD_STATION, self.load_station),
+            (AREA, self.area),
+        ]
+    }
+
+    // Items the game starts with.  Tanks and ammo count once their maximum
+    // is above zero.
+    pub fn item_set(&self) -> HashSet<Item> {
+        Equipment::from(self).item_set()
+    }
+
+    // See `Equipment::set_item`.
+    pub fn set_item(&mut self, item: Item, has: bool) -> Result<(), Error> {
+        let mut equipment = Equipment::from(&*self);
+        equipment.set_item(item, has)?;
+        self.items = equipment.items;
+        self.equipped_items = equipment.equipped_items;
+        self.beams = equipment.beams;
+        self.equipped_beams = equipment.equipped_beams;
+        Ok(())
+    }
+
+    // Starts new games at `station` instead of the landing site.
+    pub fn start_at(&mut self, station: &LoadStation) {
+        self.area = station.area as u16;
+        self.load_station = station.index;
+    }
+
+    // Returns the immediate operands to patch so the new game code uses
+    // these values, by ROM offset.  None if a changed value isn't loaded by
+    // an immediate of its own.
+    fn operand_patches(&self) -> Option<HashMap<usize, (u16, usize)>> {
+        let mut operands: HashMap<usize, (u16, usize)> = HashMap::new();
+        for (addr, value) in self.fields().iter() {
+            let store = self.stores.get(addr);
+            match store.and_then(|s| s.operand) {
+                Some((offset, size)) => {
+                    if size == 1 && *value > 0xff {
+                        return None;
+                    }
+                    // Values from the same load must stay equal.
+                    if let Some((other_value, _)) = operands.get(&offset) {
+                        if other_value != value {
+                            return None;
+                        }
+                    }
+                    operands.insert(offset, (*value, size));
+                }
+                None if store.map_or(0, |s| s.value) == *value => (),
+                None => return None,
+            }
+        }
+        Some(operands)
+    }
+
+    // Code that stores every field to WRAM, keeping A and the flags.
+    fn store_code(&self) -> Vec<u8> {
+        let mut code = vec![
+            0x08, // PHP
+            0xc2, 0x20, // REP #$20
+            0x48, // PHA
+        ];
+        for (addr, value) in self.fields().iter() {
+            code.push(0xa9); // LDA #value
+            code.extend_from_slice(&value.to_le_bytes());
+            code.push(0x8f); // STA $7e:addr
+            code.extend_from_slice(&addr.to_le_bytes());
+            code.push(0x7e);
+        }
+        code.extend_from_slice(&[
+            0x68, // PLA
+            0x28, // PLP
+        ]);
+        code
+    }
+
+    // Moves the new game code's return, and the instructions before it
+    // needed to make room for a jump, to `hook` and adds the stores between
+    // them.  The hook has to be in the same bank so moved calls still work.
+    fn write_hook(&self, w: &mut RomWriter, hook: SnesAddr) -> Result<(), Error> {
+        let entry = SnesAddr(crate::rom_addr_to_snes!(rommap::NEW_GAME_INIT));
+        if hook.bank() != entry.bank() {
+            return Err(format_err!(
+                "new game hook {} is not in bank {:02x}",
+                hook,
+                entry.bank()
+            ));
+        }
+        let listing = disasm::disassemble(w.data(), entry, Flags::default(), None)?;
+        let insts = &listing.instructions;
+        let returns: Vec<usize> = (0..insts.len())
+            .filter(|i| matches!(insts[*i].mnemonic, "RTS" | "RTL"))
+            .collect();
+        let ret = match returns[..] {
+            [ret] => ret,
+            _ => {
+                return Err(format_err!(
+                    "the new game code has {} returns, expected 1",
+                    returns.len()
+                ))
+            }
+        };
+
+        let jump_len = 3;
+        let mut start = ret;
+        let mut len = insts[ret].bytes.len();
+        while len < jump_len {
+            let prev = start
+                .checked_sub(1)
+                .filter(|prev| {
+                    let (inst, next) = (&insts[*prev], &insts[start]);
+                    inst.next_addr() == next.addr
+                        && !listing.labels.contains_key(&next.addr)
+                        && !matches!(inst.mode, Mode::Relative | Mode::RelativeLong)
+                })
+                .ok_or_else(|| format_err!("no room for a jump before {}", insts[ret].addr))?;
+            start = prev;
+            len += insts[start].bytes.len();
+        }
+
+        let mut code: Vec<u8> = insts[start..ret]
+            .iter()
+            .flat_map(|inst| inst.bytes.clone())
+            .collect();
+        code.extend(self.store_code());
+        code.extend_from_slice(&insts[ret].bytes);
+        let hook_offset = hook
+            .to_rom_offset()
+            .ok_or_else(|| format_err!("new game hook {} is not in ROM", hook))?;
+        let free = w
+            .data()
+            .get(hook_offset..hook_offset + code.len())
+            .is_some_and(|space| space.iter().all(|b| *b == 0xff));
+        if !free {
+            return Err(format_err!(
+                "no free space for the new game hook at {}",
+                hook
+            ));
+        }
+        w.write_bytes(hook_offset, &code)?;
+
+        let mut jump = vec![0x4c]; // JMP hook
+        jump.extend_from_slice(&hook.offset().to_le_bytes());
+        jump.resize(len, 0xea); // NOP
+        let site = insts[start]
+            .addr
+            .to_rom_offset()
+            .ok_or_else(|| format_err!("{} is not in ROM", insts[start].addr))?;
+        w.write_bytes(site, &jump)
+    }
+
+    // Patches the new game code to use these values.  Values the code loads
+    // as immediates of their own are changed in place.  Otherwise the code
+    // is hooked to store every field once it is done, see `write_hook`.
+    pub fn write(&self, w: &mut RomWriter) -> Result<(), Error> {
+        let operands = match self.operand_patches() {
+            Some(operands) => operands,
+            None => {
+                let hook = SnesAddr(crate::rom_addr_to_snes!(rommap::NEW_GAME_HOOK));
+                return self.write_hook(w, hook);
+            }
+        };
+        for (offset, (value, size)) in operands {
+            if size == 2 {
+                w.write_u16(offset, value)?;
+            } else {
+                w.write_u8(offset, value as u8)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(rom: &mut [u8], addr: SnesAddr, code: &[u8]) {
+        let offset = addr.to_rom_offset().unwrap();
+        rom[offset..offset + code.len()].copy_from_slice(code);
+    }
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0xff; 0x100000];
+        let entry = SnesAddr(crate::rom_addr_to_snes!(rommap::NEW_GAME_INIT));
+        put(
+            &mut rom,
+            entry,
+            &[
+                0xc2, 0x30, // REP #$30
+                0xa9, 0x63, 0x00, // LDA #$0063
+                0x8d, 0xc2, 0x09, // STA $09c2
+                0x8d, 0xc4, 0x09, // STA $09c4
+                0x9c, 0xa4, 0x09, // STZ $09a4
+                0x9c, 0xa8, 0x09, // STZ $09a8
+                0x20, 0x00, 0x90, // JSR $9000
+                0x60, // RTS
+            ],
+        );
+        put(
+            &mut rom,
+            SnesAddr::new(entry.bank(), 0x9000),
+            &[
+                0xa9, 0x05, 0x00, // LDA #$0005
+                0x8f, 0xc8, 0x09, 0x7e, // STA $7e09c8
+                0x9c, 0xc6, 0x09, // STZ $09c6
+                0x60, // RTS
+            ],
+        );
+        rom
+    }
+
+    #[test]
+    fn finds_and_patches_new_game_values() {
+        let mut rom = test_rom();
+        let mut state = StartingState::load(&rom).unwrap();
+        assert_eq!(state.energy, 99);
+        assert_eq!(state.max_energy, 99);
+        assert_eq!(state.max_missiles, 5);
+        assert_eq!(state.missiles, 0);
+        assert_eq!(state.item_set(), [Item::Missile].iter().cloned().collect());
+
+        // Values loaded as immediates are patched in place.
+        state.energy = 199;
+        state.max_energy = 199;
+        state.max_missiles = 10;
+        state.write(&mut RomWriter::new(&mut rom)).unwrap();
+        let hook = rommap::NEW_GAME_HOOK;
+        assert!(rom[hook..hook + 0x100].iter().all(|b| *b == 0xff));
+        let reloaded = StartingState::load(&rom).unwrap();
+        assert_eq!(reloaded.fields(), state.fields());
+
+        // Items are cleared with STZ and energy and max energy share a
+        // load, so these need the hook.
+        let mut state = reloaded;
+        state.set_item(Item::Morph, true).unwrap();
+        state.set_item(Item::Wave, true).unwrap();
+        state.energy = 150;
+        state.area = 1;
+        state.write(&mut RomWriter::new(&mut rom)).unwrap();
+        let mut reloaded = StartingState::load(&rom).unwrap();
+        assert_eq!(reloaded.fields(), state.fields());
+        assert_eq!(reloaded.items, 0x0004);
+
+        // Once hooked every value can be patched in place.
+        reloaded.missiles = 7;
+        reloaded.write(&mut RomWriter::new(&mut rom)).unwrap();
+        assert_eq!(StartingState::load(&rom).unwrap().missiles, 7);
+
+        // The hook is only written to free space.
+        let mut rom = test_rom();
+        rom[hook + 0x40] = 0x00;
+        assert!(state.write(&mut RomWriter::new(&mut rom)).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{format_err, Error};
+use num::FromPrimitive;
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::rommap;
+use super::util::RomWriter;
+use super::{Area, DoorData, RoomMdb, SuperMetroidData};
+
+const LOAD_STATION_SIZE: usize = 14;
+
+// A save station, elevator or debug start location.  The game spawns Samus
+// here when loading a save or starting a new game with the station's area
+// and index.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LoadStation {
+    pub area: Area,
+    // Index within the area, as stored in the save file.
+    pub index: u16,
+    pub addr: u16,     // bank $80, where this entry was loaded from
+    pub room_ptr: u16, // bank $8f
+    // The door Samus is treated as having entered through.  Selects the
+    // room state's FX and is where she goes when leaving through it.
+    pub door_ptr: u16, // bank $83
+    pub door_bts: u16,
+    // Layer 1 position in pixels.
+    pub screen_x: u16,
+    pub screen_y: u16,
+    // Samus' position relative to the screen.
+    pub samus_y: u16,
+    pub samus_x: u16,
+}
+
+impl LoadStation {
+    // Unused slots are zero filled.
+    pub fn is_used(&self) -> bool {
+        self.room_ptr != 0
+    }
+
+    pub fn rom_offset(&self) -> usize {
+        crate::rom_addr!(rommap::LOAD_STATION_BANK, self.addr)
+    }
+
+    fn read(area: Area, index: u16, addr: u16, rom: &[u8]) -> Result<LoadStation, Error> {
+        let offset = crate::rom_addr!(rommap::LOAD_STATION_BANK, addr);
+        let data = rom.get(offset..offset + LOAD_STATION_SIZE).ok_or_else(|| {
+            format_err!("load station at {:x} is past the end of the ROM", offset)
+        })?;
+        let mut r = Cursor::new(data);
+        Ok(LoadStation {
+            area,
+            index,
+            addr,
+            room_ptr: r.read_u16::<LittleEndian>()?,
+            door_ptr: r.read_u16::<LittleEndian>()?,
+            door_bts: r.read_u16::<LittleEndian>()?,
+            screen_x: r.read_u16::<LittleEndian>()?,
+            screen_y: r.read_u16::<LittleEndian>()?,
+            samus_y: r.read_u16::<LittleEndian>()?,
+            samus_x: r.read_u16::<LittleEndian>()?,
+        })
+    }
+
+    // Writes the entry back to where it was loaded from.
+    pub fn write(&self, w: &mut RomWriter) -> Result<(), Error> {
+        let offset = self.rom_offset();
+        for (i, val) in [
+            self.room_ptr,
+            self.door_ptr,
+            self.door_bts,
+            self.screen_x,
+            self.screen_y,
+            self.samus_y,
+            self.samus_x,
+        ]
+        .iter()
+        .enumerate()
+        {
+            w.write_u16(offset + i * 2, *val)?;
+        }
+        Ok(())
+    }
+}
+
+// Loads the load station lists of every area.  Each list ends where the next
+// one starts.  The last list ends at the first entry that is neither unused
+// nor in a room `is_room` accepts.
+pub fn load_stations<F>(rom: &[u8], is_room: F) -> Result<Vec<LoadStation>, Error>
+where
+    F: Fn(u16) -> bool,
+{
+    let table = rom
+        .get(rommap::LOAD_STATION_TABLE..)
+        .ok_or_else(|| format_err!("load station table is past the end of the ROM"))?;
+    let mut r = Cursor::new(table);
+    let mut ptrs = Vec::with_capacity(rommap::LOAD_STATION_AREA_COUNT);
+    for _ in 0..rommap::LOAD_STATION_AREA_COUNT {
+        ptrs.push(r.read_u16::<LittleEndian>()?);
+    }
+
+    let mut stations = Vec::new();
+    for (i, ptr) in ptrs.iter().enumerate() {
+        if *ptr < 0x8000 {
+            continue;
+        }
+        let area = Area::from_usize(i).ok_or_else(|| format_err!("unknown area {}", i))?;
+        let end = ptrs.iter().filter(|p| *p > ptr).min();
+        let count = match end {
+            Some(end) => (end - ptr) as usize / LOAD_STATION_SIZE,
+            None => rommap::MAX_LOAD_STATIONS_PER_AREA,
+        };
+        for index in 0..count {
+            // Lists can't run past the end of the bank.
+            let addr = match ptr.checked_add((index * LOAD_STATION_SIZE) as u16) {
+                Some(addr) if addr.checked_add(LOAD_STATION_SIZE as u16 - 1).is_some() => addr,
+                _ => break,
+            };
+            let station = LoadStation::read(area, index as u16, addr, rom);
+            if end.is_none() {
+                match station {
+                    Ok(ref s) if !s.is_used() || is_room(s.room_ptr) => (),
+                    _ => break,
+                }
+            }
+            stations.push(station?);
+        }
+    }
+    Ok(stations)
+}
+
+impl SuperMetroidData {
+    pub fn load_station(&self, area: Area, index: u16) -> Option<&LoadStation> {
+        self.load_stations
+            .iter()
+            .find(|s| s.area == area && s.index == index)
+    }
+
+    pub fn load_station_room(&self, station: &LoadStation) -> Option<&RoomMdb> {
+        self.room_mdb.get(&station.room_ptr)
+    }
+
+    // Returns the door the station's room is entered through.  Doors are
+    // found by their pointer in the door lists of all rooms.
+    pub fn load_station_door(&self, station: &LoadStation) -> Option<&DoorData> {
+        if station.door_ptr == 0 {
+            return None;
+        }
+        self.room_mdb
+            .values()
+            .flat_map(|room| room.door_list.iter())
+            .find(|door| door.door_ptr == station.door_ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add_room, door};
+
+    fn put_station(rom: &mut [u8], addr: u16, room_ptr: u16, door_ptr: u16) {
+        let mut w = RomWriter::new(rom);
+        let offset = crate::rom_addr!(rommap::LOAD_STATION_BANK, addr);
+        w.write_u16(offset, room_ptr).unwrap();
+        w.write_u16(offset + 2, door_ptr).unwrap();
+        w.write_u16(offset + 6, 0x0100).unwrap();
+    }
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        let mut w = RomWriter::new(&mut rom);
+        // Crateria has two stations and the second is unused, Brinstar has
+        // one and the debug area's list runs until an unknown room.
+        let ptrs = [0xc4c5, 0xc4e1, 0, 0, 0, 0, 0, 0xc4ef];
+        for (i, ptr) in ptrs.iter().enumerate() {
+            w.write_u16(rommap::LOAD_STATION_TABLE + i * 2, *ptr)
+                .unwrap();
+        }
+        put_station(&mut rom, 0xc4c5, 0x91f8, 0x88fe);
+        put_station(&mut rom, 0xc4e1, 0x9ad9, 0x8e86);
+        put_station(&mut rom, 0xc4ef, 0x91f8, 0);
+        put_station(&mut rom, 0xc4fd, 0x1234, 0);
+        rom
+    }
+
+    #[test]
+    fn stations_load_link_and_write() {
+        let mut rom = test_rom();
+        let mut sm = SuperMetroidData::default();
+        let mut landing_door = door(0x91f8, 0, 0);
+        landing_door.door_ptr = 0x88fe;
+        add_room(&mut sm, 0x92fd, 1, &[], vec![landing_door]);
+        add_room(&mut sm, 0x91f8, 1, &[], vec![]);
+        sm.load_stations = load_stations(&rom, |ptr| sm.room_mdb.contains_key(&ptr)).unwrap();
+
+        let found: Vec<(Area, u16, bool)> = sm
+            .load_stations
+            .iter()
+            .map(|s| (s.area, s.index, s.is_used()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Area::Crateria, 0, true),
+                (Area::Crateria, 1, false),
+                (Area::Brinstar, 0, true),
+                (Area::Debug, 0, true),
+            ]
+        );
+
+        let station = sm.load_station(Area::Crateria, 0).unwrap();
+        assert_eq!(station.screen_x, 0x0100);
+        assert!(sm.load_station_room(station).is_some());
+        assert_eq!(sm.load_station_door(station).unwrap().door_ptr, 0x88fe);
+        assert!(sm
+            .load_station_door(sm.load_station(Area::Brinstar, 0).unwrap())
+            .is_none());
+
+        let mut station = station.clone();
+        station.room_ptr = 0x92fd;
+        station.samus_x = 0x0080;
+        station.write(&mut RomWriter::new(&mut rom)).unwrap();
+        let reloaded = load_stations(&rom, |_| true).unwrap();
+        assert_eq!(reloaded[0], station);
+
+        // A list at the end of the bank stops there.
+        let mut rom = vec![0; 0x10000];
+        RomWriter::new(&mut rom)
+            .write_u16(rommap::LOAD_STATION_TABLE, 0xffe0)
+            .unwrap();
+        put_station(&mut rom, 0xffe0, 0x91f8, 0);
+        let stations = load_stations(&rom, |_| true).unwrap();
+        assert_eq!(stations.len(), 2);
+    }
+}
@@ -0,0 +1,195 @@
+use failure::{format_err, Error};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolFormat {
+    // WLA-DX symbol file as also written by bsnes.  `bb:aaaa label` lines,
+    // optionally in a `[labels]` section.
+    Sym,
+    // Mesen label file.  `Type:offset:label[:comment]` lines.
+    Mlb,
+}
+
+impl SymbolFormat {
+    // Guesses the format from a file's extension.
+    pub fn from_path(path: &Path) -> Option<SymbolFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "sym" => Some(SymbolFormat::Sym),
+            "mlb" => Some(SymbolFormat::Mlb),
+            _ => None,
+        }
+    }
+}
+
+// Labels keyed by 24 bit SNES address.  ROM addresses are stored in their
+// $80-$FF bank mirror so $00:8000 and $80:8000 are the same symbol.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: BTreeMap<u32, String>,
+}
+
+fn normalize(addr: u32) -> u32 {
+    let bank = addr >> 16;
+    if addr & 0x8000 != 0 && bank < 0x7e {
+        addr | 0x80_0000
+    } else {
+        addr
+    }
+}
+
+fn parse_hex(s: &str, line: &str) -> Result<u32, Error> {
+    u32::from_str_radix(s, 16).map_err(|e| format_err!("bad address in \"{}\": {}", line, e))
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        Default::default()
+    }
+
+    pub fn load(path: &Path) -> Result<SymbolTable, Error> {
+        let format = SymbolFormat::from_path(path)
+            .ok_or_else(|| format_err!("unknown symbol file type {}", path.display()))?;
+        Self::parse(format, &std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(format: SymbolFormat, text: &str) -> Result<SymbolTable, Error> {
+        match format {
+            SymbolFormat::Sym => Self::from_sym(text),
+            SymbolFormat::Mlb => Self::from_mlb(text),
+        }
+    }
+
+    pub fn from_sym(text: &str) -> Result<SymbolTable, Error> {
+        let mut table = SymbolTable::new();
+        // Files without sections are all labels.
+        let mut in_labels = true;
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_labels = line.eq_ignore_ascii_case("[labels]");
+                continue;
+            }
+            if !in_labels {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let addr = parts.next().unwrap_or("");
+            let name = parts
+                .next()
+                .ok_or_else(|| format_err!("missing label in \"{}\"", line))?;
+            let mut addr_parts = addr.split(':');
+            let (bank, offset) = match (addr_parts.next(), addr_parts.next()) {
+                (Some(bank), Some(offset)) => (bank, offset),
+                _ => return Err(format_err!("bad address in \"{}\"", line)),
+            };
+            let addr = (parse_hex(bank, line)? << 16) | (parse_hex(offset, line)? & 0xffff);
+            table.insert(addr, name);
+        }
+        Ok(table)
+    }
+
+    pub fn from_mlb(text: &str) -> Result<SymbolTable, Error> {
+        let mut table = SymbolTable::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(4, ':');
+            let (kind, offset, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kind), Some(offset), Some(name)) => (kind, offset, name),
+                _ => return Err(format_err!("bad Mesen label \"{}\"", line)),
+            };
+            // Comment only entries have no label.
+            if name.is_empty() {
+                continue;
+            }
+            // Ranges use the label for their first byte.
+            let offset = parse_hex(offset.split('-').next().unwrap_or(""), line)? as usize;
+            let addr = match kind {
+                "SnesPrgRom" | "PRG" => crate::rom_addr_to_snes!(offset),
+                "SnesWorkRam" | "WORK" => 0x7e_0000 + offset as u32,
+                // Registers, SRAM and other memory types can't hold code.
+                _ => continue,
+            };
+            table.insert(addr, name);
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, addr: u32, name: &str) {
+        self.symbols.insert(normalize(addr), name.to_string());
+    }
+
+    pub fn lookup(&self, addr: u32) -> Option<&str> {
+        self.symbols.get(&normalize(addr)).map(String::as_str)
+    }
+
+    // Names an address after the closest symbol at or before it in the same
+    // bank, as `label` or `label+0x12`.
+    pub fn resolve(&self, addr: u32) -> Option<String> {
+        let addr = normalize(addr);
+        let (sym_addr, name) = self.symbols.range(..=addr).next_back()?;
+        if sym_addr >> 16 != addr >> 16 {
+            return None;
+        }
+        Some(match addr - sym_addr {
+            0 => name.clone(),
+            offset => format!("{}+0x{:x}", name, offset),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sym_files() {
+        let text = "; wla symbolic information file\n\
+                    [labels]\n\
+                    a0:8000 CommonEnemyRoutines\n\
+                    00:8687 InitAi_Sidehopper ; comment\n\
+                    \n\
+                    [definitions]\n\
+                    00000010 SOME_DEFINE\n";
+        let table = SymbolTable::from_sym(text).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.lookup(0x80_8687), Some("InitAi_Sidehopper"));
+        assert_eq!(table.lookup(0xa0_8000), Some("CommonEnemyRoutines"));
+        assert_eq!(
+            table.resolve(0xa0_8010),
+            Some("CommonEnemyRoutines+0x10".to_string())
+        );
+        assert_eq!(table.resolve(0xa1_8010), None);
+        assert!(SymbolTable::from_sym("a0:80x0 bad").is_err());
+    }
+
+    #[test]
+    fn mlb_files() {
+        let text = "SnesPrgRom:100000:MainAi_Zoomer\n\
+                    SnesPrgRom:100010-10001f:Table:some bytes\n\
+                    SnesWorkRam:09c2:SamusHealth\n\
+                    SnesRegister:2100:INIDISP\n\
+                    SnesPrgRom:100020::comment only\n";
+        let table = SymbolTable::from_mlb(text).unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.lookup(0xa0_8000), Some("MainAi_Zoomer"));
+        assert_eq!(table.lookup(0xa0_8010), Some("Table"));
+        assert_eq!(table.lookup(0x7e_09c2), Some("SamusHealth"));
+    }
+}
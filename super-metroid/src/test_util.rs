@@ -0,0 +1,92 @@
+// Helpers for building synthetic game data in unit tests.
+use super::*;
+
+pub fn door(dest_room_ptr: u16, screen_x: u8, screen_y: u8) -> DoorData {
+    DoorData {
+        door_ptr: 0x0000,
+        dest_room_ptr,
+        elevator_props: 0,
+        orientation: 0,
+        x: (screen_x as u16) << 8,
+        y: (screen_y as u16) << 8,
+        spawn_dist: 0x8000,
+        asm_ptr: 0x0000,
+    }
+}
+
+// Builds a room with door blocks for `doors` (door index, screen x).
+pub fn add_room<'a>(
+    sm: &'a mut SuperMetroidData,
+    ptr: u16,
+    width: u8,
+    doors: &[(u8, usize)],
+    door_list: Vec<DoorData>,
+) -> &'a mut RoomMdb {
+    let blocks_w = width as usize * 16;
+    let num_blocks = blocks_w * 16;
+    let mut layer_1 = Vec::new();
+    let mut bts = vec![0; num_blocks];
+    for _ in 0..num_blocks {
+        layer_1.push(BlockInfo {
+            ty: BlockType::SolidBlock,
+            x_flip: false,
+            y_flip: false,
+            tile_index: 0,
+        });
+    }
+    for (index, screen_x) in doors {
+        let i = (4 + *index as usize) * blocks_w + screen_x * 16;
+        layer_1[i].ty = BlockType::DoorBlock;
+        bts[i] = *index;
+    }
+
+    let level_data = 0xc2_0000 + ptr as u32;
+    sm.level_data.insert(
+        level_data,
+        RoomData {
+            layer_1,
+            bts,
+            layer_2: None,
+            num_doors: door_list.len(),
+        },
+    );
+    sm.room_mdb.insert(
+        ptr,
+        RoomMdb {
+            index: 0,
+            area: Area::Crateria,
+            x: 0,
+            y: 0,
+            width,
+            height: 1,
+            up_scroller: 0x70,
+            down_scroller: 0xa0,
+            graphics_flags: 0,
+            door_list_ptr: 0,
+            states: vec![State {
+                condition: StateCondition::Default,
+                data: StateData {
+                    level_data,
+                    tile_set: TileSet::UpperCrateria,
+                    music_data_index: 0,
+                    music_track: 0,
+                    fx_ptr: 0,
+                    enemy_population: 0,
+                    enemy_set: 0,
+                    layer_2_scroll_x: 0,
+                    layer_2_scroll_y: 0,
+                    scroll_ptr: 0,
+                    x_ray_block_ptr: 0,
+                    main_asm_ptr: 0,
+                    plm_ptr: 0,
+                    bg_ptr: 0,
+                    setup_asm_ptr: 0,
+                },
+            }],
+            door_list,
+            door_reachable: true,
+        },
+    );
+    sm.room_order.push(ptr);
+    sm.room_mdb.get_mut(&ptr).unwrap()
+}
@@ -0,0 +1,310 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::rommap;
+
+// Message box tilemaps are rows of 32 BG3 tiles.
+pub const ROW_WORDS: usize = 32;
+const TILE_MASK: u16 = 0x3ff;
+const ATTR_MASK: u16 = !TILE_MASK;
+const MESSAGE_DEFINITION_SIZE: usize = 6;
+
+// Digits share the HUD's tiles, the same ones its digit table at $80:9dbf
+// uses.  The tile for 0 comes after the one for 9.
+const MESSAGE_BOX_DIGITS: [u16; 10] = [0x09, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+// Punctuation and arrows of the message box font.
+const MESSAGE_BOX_SYMBOLS: &[(u16, char)] = &[
+    (0xcb, '\''),
+    (0xcc, '-'),
+    (0xcd, '!'),
+    (0xce, '?'),
+    (0xcf, '.'),
+    (0xd0, '←'),
+    (0xd1, '→'),
+    (0xd2, '↑'),
+    (0xd3, '↓'),
+];
+
+// Maps BG3 tile numbers to characters.
+#[derive(Clone, Debug)]
+pub struct Charset {
+    chars: Vec<(u16, char)>,
+    // Tile used to pad text out to the width of the box.
+    blank_tile: u16,
+}
+
+impl Charset {
+    // The message box font.  Letters start at tile $e0 and spaces between
+    // words use tile $0f.
+    pub fn message_box() -> Charset {
+        let mut chars = vec![(0x0f, ' ')];
+        chars.extend((b'A'..=b'Z').map(|c| (0xe0 + (c - b'A') as u16, c as char)));
+        chars.extend(
+            MESSAGE_BOX_DIGITS
+                .iter()
+                .zip('0'..='9')
+                .map(|(tile, c)| (*tile, c)),
+        );
+        chars.extend_from_slice(MESSAGE_BOX_SYMBOLS);
+        Charset {
+            chars,
+            blank_tile: 0x4e,
+        }
+    }
+
+    // Adds or replaces a character.
+    pub fn insert(&mut self, tile: u16, c: char) {
+        self.chars.retain(|(t, ch)| *t != tile && *ch != c);
+        self.chars.push((tile, c));
+    }
+
+    pub fn char_for(&self, tile: u16) -> Option<char> {
+        if tile == self.blank_tile {
+            return Some(' ');
+        }
+        self.chars.iter().find(|(t, _)| *t == tile).map(|(_, c)| *c)
+    }
+
+    pub fn tile_for(&self, c: char) -> Option<u16> {
+        self.chars.iter().find(|(_, ch)| *ch == c).map(|(t, _)| *t)
+    }
+
+    fn is_text(&self, word: u16) -> bool {
+        self.char_for(word & TILE_MASK).is_some()
+    }
+
+    fn is_letter(&self, word: u16) -> bool {
+        self.char_for(word & TILE_MASK).is_some_and(|c| c != ' ')
+    }
+}
+
+// Decodes a tilemap one row per line.  Tiles that are not characters, such
+// as the box's borders and button graphics, become spaces.  Lines are
+// trimmed and empty ones are left out.
+pub fn decode_tilemap(words: &[u16], charset: &Charset) -> String {
+    words
+        .chunks(ROW_WORDS)
+        .map(|row| {
+            row.iter()
+                .map(|w| charset.char_for(w & TILE_MASK).unwrap_or(' '))
+                .collect::<String>()
+                .trim()
+                .to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Message {
+    // Message box number as passed to the message box routine.  Starts at 1.
+    pub index: u8,
+    pub tilemap_ptr: u16, // bank $85
+    pub tilemap: Vec<u16>,
+    pub text: String,
+}
+
+impl Message {
+    // ROM offset of the tilemap.
+    pub fn rom_offset(&self) -> usize {
+        crate::rom_addr!(rommap::MESSAGE_BANK, self.tilemap_ptr)
+    }
+
+    // Returns the tilemap with each line of `text` centered in one of the
+    // message's text rows.  The tilemap keeps its size so it can be written
+    // back in place.
+    pub fn encode(&self, text: &str, charset: &Charset) -> Result<Vec<u16>, Error> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut tilemap = self.tilemap.clone();
+
+        let mut rows = tilemap
+            .chunks_mut(ROW_WORDS)
+            .filter(|row| row.iter().any(|w| charset.is_letter(*w)));
+        let mut row_count = 0;
+        for (i, row) in rows.by_ref().enumerate() {
+            let line = lines.get(i).cloned().unwrap_or("");
+            encode_row(row, line, charset)?;
+            row_count += 1;
+        }
+        if lines.len() > row_count {
+            return Err(format_err!(
+                "message {} has {} text rows, got {} lines",
+                self.index,
+                row_count,
+                lines.len()
+            ));
+        }
+        Ok(tilemap)
+    }
+}
+
+// Replaces the text in a row.  The text area runs from the first to the
+// last character tile and takes the attributes of the row's first letter.
+fn encode_row(row: &mut [u16], line: &str, charset: &Charset) -> Result<(), Error> {
+    let start = row.iter().position(|w| charset.is_text(*w)).unwrap_or(0);
+    let end = row
+        .iter()
+        .rposition(|w| charset.is_text(*w))
+        .map_or(0, |i| i + 1);
+    let attr = row
+        .iter()
+        .find(|w| charset.is_letter(**w))
+        .map_or(0, |w| w & ATTR_MASK);
+
+    let width = end - start;
+    let len = line.chars().count();
+    if len > width {
+        return Err(format_err!(
+            "\"{}\" is longer than the {} characters that fit",
+            line,
+            width
+        ));
+    }
+    let mut tiles = vec![charset.blank_tile; width];
+    let pad = (width - len) / 2;
+    for (i, c) in line.chars().enumerate() {
+        tiles[pad + i] = charset
+            .tile_for(c)
+            .ok_or_else(|| format_err!("'{}' is not in the charset", c))?;
+    }
+    for (word, tile) in row[start..end].iter_mut().zip(tiles) {
+        *word = attr | tile;
+    }
+    Ok(())
+}
+
+fn read_ptr(r: &mut Cursor<&[u8]>) -> Result<u16, Error> {
+    Ok(r.read_u16::<LittleEndian>()?)
+}
+
+// Loads the message box table.  A message's tilemap ends where the next
+// message's starts so the table has one more entry than there are messages.
+pub fn load_messages(rom: &[u8], charset: &Charset) -> Result<Vec<Message>, Error> {
+    let table = rom
+        .get(
+            rommap::MESSAGE_DEFINITIONS
+                ..rommap::MESSAGE_DEFINITIONS
+                    + (rommap::MESSAGE_COUNT + 1) * MESSAGE_DEFINITION_SIZE,
+        )
+        .ok_or_else(|| format_err!("message table out of range"))?;
+    let mut r = Cursor::new(table);
+    let mut ptrs = Vec::with_capacity(rommap::MESSAGE_COUNT + 1);
+    for _ in 0..=rommap::MESSAGE_COUNT {
+        let _setup = read_ptr(&mut r)?;
+        let _draw = read_ptr(&mut r)?;
+        ptrs.push(read_ptr(&mut r)?);
+    }
+
+    let mut messages = Vec::with_capacity(rommap::MESSAGE_COUNT);
+    for (i, pair) in ptrs.windows(2).enumerate() {
+        let (ptr, next) = (pair[0], pair[1]);
+        if ptr < 0x8000 || next < ptr || (next - ptr) % 2 != 0 {
+            return Err(format_err!(
+                "bad tilemap bounds {:04x}-{:04x} for message {}",
+                ptr,
+                next,
+                i + 1
+            ));
+        }
+        let start = crate::rom_addr!(rommap::MESSAGE_BANK, ptr);
+        let data = rom
+            .get(start..start + (next - ptr) as usize)
+            .ok_or_else(|| format_err!("message {} out of range", i + 1))?;
+        let mut r = Cursor::new(data);
+        let tilemap = (0..data.len() / 2)
+            .map(|_| r.read_u16::<LittleEndian>())
+            .collect::<Result<Vec<u16>, _>>()?;
+        messages.push(Message {
+            index: i as u8 + 1,
+            tilemap_ptr: ptr,
+            text: decode_tilemap(&tilemap, charset),
+            tilemap,
+        });
+    }
+    Ok(messages)
+}
+
+// Little endian bytes of a tilemap, as stored in ROM.
+pub fn tilemap_bytes(words: &[u16]) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|w| w.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_row(charset: &Charset, text: &str) -> Vec<u16> {
+        // Border tiles, padding and then the text.
+        let mut row = vec![0x000e; 3];
+        row.extend(vec![0x284e; 3]);
+        for c in text.chars() {
+            let tile = charset.tile_for(c).unwrap();
+            row.push(if c == ' ' { 0x2c00 } else { 0x2800 } | tile);
+        }
+        row.resize(ROW_WORDS - 3, 0x284e);
+        row.resize(ROW_WORDS, 0x000e);
+        row
+    }
+
+    #[test]
+    fn decodes_and_encodes_messages() {
+        let charset = Charset::message_box();
+        let mut tilemap = vec![0x000e; ROW_WORDS];
+        tilemap.extend(test_row(&charset, "ENERGY TANK"));
+        let message = Message {
+            index: 1,
+            tilemap_ptr: 0x877f,
+            text: decode_tilemap(&tilemap, &charset),
+            tilemap,
+        };
+        assert_eq!(message.text, "ENERGY TANK");
+        assert_eq!(message.tilemap[ROW_WORDS + 6], 0x28e4);
+
+        let encoded = message.encode("P2 MISSILE!", &charset).unwrap();
+        assert_eq!(decode_tilemap(&encoded, &charset), "P2 MISSILE!");
+        assert_eq!(encoded[ROW_WORDS + 3 + 7 + 1], 0x2801);
+        assert!(message.encode("P2 MISSILE;", &charset).is_err());
+        let encoded = message.encode("BOBS MISSILE", &charset).unwrap();
+        assert_eq!(encoded.len(), message.tilemap.len());
+        assert_eq!(decode_tilemap(&encoded, &charset), "BOBS MISSILE");
+        // The border is kept.
+        assert_eq!(&encoded[ROW_WORDS..ROW_WORDS + 3], &[0x000e; 3]);
+        // Centered in the 26 tiles between the borders.
+        assert_eq!(encoded[ROW_WORDS + 3 + 7], 0x28e1);
+        assert_eq!(encoded[ROW_WORDS + 3 + 6], 0x284e);
+
+        let long = "A".repeat(ROW_WORDS);
+        assert!(message.encode(&long, &charset).is_err());
+        assert!(message.encode("ONE\nTWO", &charset).is_err());
+    }
+
+    #[test]
+    fn loads_message_table() {
+        let charset = Charset::message_box();
+        let mut rom = vec![0; 0x30000];
+        let words = test_row(&charset, "MISSILE");
+        let bytes = tilemap_bytes(&words);
+        let mut ptr = 0x9000u16;
+        for i in 0..=rommap::MESSAGE_COUNT {
+            let entry = rommap::MESSAGE_DEFINITIONS + i * MESSAGE_DEFINITION_SIZE;
+            rom[entry + 4..entry + 6].copy_from_slice(&ptr.to_le_bytes());
+            if i < rommap::MESSAGE_COUNT {
+                let start = crate::rom_addr!(rommap::MESSAGE_BANK, ptr);
+                rom[start..start + bytes.len()].copy_from_slice(&bytes);
+                ptr += bytes.len() as u16;
+            }
+        }
+
+        let messages = load_messages(&rom, &charset).unwrap();
+        assert_eq!(messages.len(), rommap::MESSAGE_COUNT);
+        assert_eq!(messages[1].index, 2);
+        assert_eq!(messages[1].tilemap_ptr, 0x9040);
+        assert_eq!(messages[1].text, "MISSILE");
+    }
+}
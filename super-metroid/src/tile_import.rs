@@ -0,0 +1,307 @@
+use failure::{format_err, Error};
+use std::collections::HashMap;
+
+use super::buffer::{IndexedBuffer, RgbaBuffer};
+use super::graphics::{TileRenderer, BYTES_PER_TILE, TILE_H, TILE_W};
+use super::{Color, TileTable, TileTableEntry, Tiles};
+
+// Largest tile index a tile table entry can hold.
+const MAX_TILE_INDEX: usize = 0x3ff;
+
+// Converts an image made with one 16 color sub palette into packed 4bpp
+// tiles, read left to right then top to bottom.  Transparent pixels use
+// color 0.  Opaque pixels are matched against the rest of `colors` after
+// quantising them the way the SNES would, so a color repeated in slot 0 and
+// a later slot doesn't become transparent.
+pub fn tiles_from_image(img: &RgbaBuffer, colors: &[Color]) -> Result<Tiles, Error> {
+    let (w, h) = (img.width, img.height);
+    if w % TILE_W != 0 || h % TILE_H != 0 {
+        return Err(format_err!(
+            "image size {}x{} is not a multiple of the tile size",
+            w,
+            h
+        ));
+    }
+    if colors.len() > 16 {
+        return Err(format_err!(
+            "4bpp tiles can use 16 colors, got {}",
+            colors.len()
+        ));
+    }
+
+    let tiles_w = w / TILE_W;
+    let tiles_h = h / TILE_H;
+    let mut data = vec![0; tiles_w * tiles_h * BYTES_PER_TILE];
+    for y in 0..h {
+        for x in 0..w {
+            let pixel = img.get_pixel(x, y);
+            let val = if pixel[3] == 0 {
+                0
+            } else {
+                let color = Color::from_bgr555(
+                    Color {
+                        r: pixel[0],
+                        g: pixel[1],
+                        b: pixel[2],
+                    }
+                    .to_bgr555(),
+                );
+                let index = colors.iter().skip(1).position(|c| *c == color);
+                index.map(|i| i + 1).ok_or_else(|| {
+                    format_err!(
+                        "color #{:02x}{:02x}{:02x} at ({}, {}) is not in the palette",
+                        pixel[0],
+                        pixel[1],
+                        pixel[2],
+                        x,
+                        y
+                    )
+                })? as u8
+            };
+
+            let tile = (y / TILE_H) * tiles_w + x / TILE_W;
+            let (tile_x, tile_y) = (x % TILE_W, y % TILE_H);
+            let offset = tile * BYTES_PER_TILE + tile_y * 4 + tile_x / 2;
+            data[offset] |= if tile_x & 0x1 == 0x1 { val << 4 } else { val };
+        }
+    }
+
+    Ok(Tiles { data })
+}
+
+// Converts an indexed image such as one from
+// `TileRenderer::render_room_indexed` back into packed 4bpp tiles, read left
+// to right then top to bottom.  Returns the tiles and the palette row each
+// tile uses.  Every pixel of a tile must come from the same 16 color row,
+// except for index 0 of a row which is transparent in any of them.
+pub fn tiles_from_indexed(img: &IndexedBuffer) -> Result<(Tiles, Vec<u8>), Error> {
+    let (w, h) = (img.width, img.height);
+    if w % TILE_W != 0 || h % TILE_H != 0 {
+        return Err(format_err!(
+            "image size {}x{} is not a multiple of the tile size",
+            w,
+            h
+        ));
+    }
+
+    let tiles_w = w / TILE_W;
+    let tiles_h = h / TILE_H;
+    let mut data = vec![0; tiles_w * tiles_h * BYTES_PER_TILE];
+    let mut rows = vec![None; tiles_w * tiles_h];
+    for y in 0..h {
+        for x in 0..w {
+            let index = img.get_pixel(x, y);
+            let (row, val) = (index >> 4, index & 0xf);
+            let tile = (y / TILE_H) * tiles_w + x / TILE_W;
+            if val != 0 {
+                match rows[tile] {
+                    None => rows[tile] = Some(row),
+                    Some(r) if r != row => {
+                        return Err(format_err!(
+                            "tile at ({}, {}) uses palette rows {} and {}",
+                            x / TILE_W * TILE_W,
+                            y / TILE_H * TILE_H,
+                            r,
+                            row
+                        ))
+                    }
+                    _ => (),
+                }
+            }
+
+            let (tile_x, tile_y) = (x % TILE_W, y % TILE_H);
+            let offset = tile * BYTES_PER_TILE + tile_y * 4 + tile_x / 2;
+            data[offset] |= if tile_x & 0x1 == 0x1 { val << 4 } else { val };
+        }
+    }
+
+    // Fully transparent tiles can use any row.
+    let rows = rows.iter().map(|r| r.unwrap_or(0)).collect();
+    Ok((Tiles { data }, rows))
+}
+
+// Returns a packed 4bpp tile flipped horizontally and/or vertically.
+pub fn flip_tile(tile: &[u8], flip_h: bool, flip_v: bool) -> [u8; BYTES_PER_TILE] {
+    let mut flipped = [0; BYTES_PER_TILE];
+    for y in 0..8 {
+        for x in 0..8 {
+            let src_x = if flip_h { 7 - x } else { x };
+            let src_y = if flip_v { 7 - y } else { y };
+            let val = TileRenderer::get_pixel(tile, src_x, src_y);
+            flipped[y * 4 + x / 2] |= if x & 0x1 == 0x1 { val << 4 } else { val };
+        }
+    }
+    flipped
+}
+
+// Removes duplicate tiles, including tiles that are flips of earlier ones.
+// Returns the unique tiles and a tile table that rebuilds the input from
+// them, one entry per input tile, using sub palette `palette`.
+pub fn dedupe_tiles(tiles: &Tiles, palette: u8) -> Result<(Tiles, TileTable), Error> {
+    let mut unique = Vec::new();
+    let mut indices: HashMap<[u8; BYTES_PER_TILE], u16> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for tile in tiles.data.chunks_exact(BYTES_PER_TILE) {
+        // An input tile that is a flip of a unique tile is that tile drawn
+        // with the same flip.
+        let found = [(false, false), (true, false), (false, true), (true, true)]
+            .iter()
+            .find_map(|(flip_h, flip_v)| {
+                let index = indices.get(&flip_tile(tile, *flip_h, *flip_v))?;
+                Some((*index, *flip_h, *flip_v))
+            });
+
+        let (index, flip_h, flip_v) = match found {
+            Some(found) => found,
+            None => {
+                let index = indices.len();
+                if index > MAX_TILE_INDEX {
+                    return Err(format_err!("more than {} unique tiles", MAX_TILE_INDEX + 1));
+                }
+                let mut key = [0; BYTES_PER_TILE];
+                key.copy_from_slice(tile);
+                indices.insert(key, index as u16);
+                unique.extend_from_slice(tile);
+                (index as u16, false, false)
+            }
+        };
+
+        entries.push(TileTableEntry {
+            index,
+            palette,
+            priority: false,
+            flip_h,
+            flip_v,
+        });
+    }
+
+    Ok((Tiles { data: unique }, TileTable { entries }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::graphics::{de_planar_tiles, planarize_tiles};
+    use super::*;
+
+    fn test_tile() -> Vec<u8> {
+        // A diagonal of increasing colors that is not symmetric.
+        let mut tile = vec![0; BYTES_PER_TILE];
+        for i in 0..8 {
+            tile[i * 4 + i / 2] |= if i & 1 == 1 {
+                (i as u8 + 8) << 4
+            } else {
+                i as u8 + 1
+            };
+        }
+        tile
+    }
+
+    #[test]
+    fn planarize_round_trips() {
+        let tile = test_tile();
+        let mut data = tile.clone();
+        planarize_tiles(&mut data);
+        assert_ne!(data, tile);
+        de_planar_tiles(&mut data);
+        assert_eq!(data, tile);
+    }
+
+    #[test]
+    fn flipped_tiles_are_deduped() {
+        let tile = test_tile();
+        let mut data = tile.clone();
+        data.extend_from_slice(&flip_tile(&tile, true, false));
+        data.extend_from_slice(&flip_tile(&tile, true, true));
+        data.extend_from_slice(&[0x11; BYTES_PER_TILE]);
+        data.extend_from_slice(&tile);
+
+        let (unique, table) = dedupe_tiles(&Tiles { data }, 3).unwrap();
+        assert_eq!(unique.data.len(), 2 * BYTES_PER_TILE);
+        let entries: Vec<(u16, bool, bool)> = table
+            .entries
+            .iter()
+            .map(|e| (e.index, e.flip_h, e.flip_v))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (0, false, false),
+                (0, true, false),
+                (0, true, true),
+                (1, false, false),
+                (0, false, false),
+            ]
+        );
+        assert!(table.entries.iter().all(|e| e.palette == 3));
+    }
+
+    #[test]
+    fn image_colors_must_be_in_palette() {
+        let colors: Vec<Color> = (0..16u16).map(|i| Color::from_bgr555(i * 2)).collect();
+        let mut img = RgbaBuffer::new(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                // Components are quantised so 0x13 matches 0x10.
+                img.put_pixel(x, y, [(x as u8 * 2) << 3 | 0x3, 0, 0, 0xff]);
+            }
+            img.put_pixel(0, y, [0xff, 0xff, 0xff, 0x00]);
+        }
+
+        let tiles = tiles_from_image(&img, &colors).unwrap();
+        assert_eq!(tiles.data.len(), 2 * BYTES_PER_TILE);
+        assert_eq!(TileRenderer::get_pixel(&tiles.data, 0, 0), 0);
+        assert_eq!(TileRenderer::get_pixel(&tiles.data, 5, 3), 5);
+        assert_eq!(
+            TileRenderer::get_pixel(&tiles.data[BYTES_PER_TILE..], 1, 0),
+            9
+        );
+
+        img.put_pixel(3, 2, [0x00, 0xff, 0x00, 0xff]);
+        let err = tiles_from_image(&img, &colors).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "color #00ff00 at (3, 2) is not in the palette"
+        );
+    }
+
+    #[test]
+    fn opaque_pixels_skip_the_transparent_color() {
+        // Black is both the transparent color and color 7.
+        let mut colors: Vec<Color> = (0..16u16).map(|i| Color::from_bgr555(i * 2)).collect();
+        colors[7] = colors[0].clone();
+        let mut img = RgbaBuffer::new(8, 8);
+        img.put_pixel(1, 0, [0x00, 0x00, 0x00, 0xff]);
+        img.put_pixel(2, 0, [0x00, 0x00, 0x00, 0x00]);
+
+        let tiles = tiles_from_image(&img, &colors).unwrap();
+        assert_eq!(TileRenderer::get_pixel(&tiles.data, 1, 0), 7);
+        assert_eq!(TileRenderer::get_pixel(&tiles.data, 2, 0), 0);
+
+        // Without a repeat an opaque pixel can't use color 0.
+        colors[7] = Color::from_bgr555(7 * 2);
+        assert!(tiles_from_image(&img, &colors).is_err());
+    }
+
+    #[test]
+    fn indexed_images_round_trip() {
+        let tile = test_tile();
+        let palette: Vec<Color> = (0..64u16).map(Color::from_bgr555).collect();
+        let mut img = IndexedBuffer::new(16, 8, &palette);
+        TileRenderer::render_tile(&tile, &mut img, &palette, 2, 0, 0, false, false).unwrap();
+        TileRenderer::render_tile(&tile, &mut img, &palette, 3, 8, 0, true, false).unwrap();
+        assert_eq!(img.get_pixel(1, 1), 0x20 + 9);
+
+        let (tiles, rows) = tiles_from_indexed(&img).unwrap();
+        assert_eq!(rows, vec![2, 3]);
+        assert_eq!(&tiles.data[..BYTES_PER_TILE], &tile[..]);
+        assert_eq!(
+            &tiles.data[BYTES_PER_TILE..],
+            &flip_tile(&tile, true, false)[..]
+        );
+
+        img.put_pixel(3, 3, 0x11);
+        let err = tiles_from_indexed(&img).unwrap_err();
+        assert_eq!(err.to_string(), "tile at (0, 0) uses palette rows 2 and 1");
+    }
+}
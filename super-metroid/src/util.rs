@@ -1,3 +1,4 @@
+use failure::{format_err, Error};
 use std::io;
 use std::io::prelude::*;
 use std::io::{Cursor, Read};
@@ -26,6 +27,75 @@ impl<'a> Read for RomReader<'a> {
     }
 }
 
+// Reads a little endian word at `offset`.
+pub fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or_else(|| format_err!("read at {:x} is past the end of the data", offset))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+// Checks that a table about to be written has as many entries as the space
+// it is written to.
+pub fn check_len(name: &str, len: usize, expected: usize) -> Result<(), Error> {
+    if len == expected {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "{} has {} entries, expected {}",
+            name,
+            len,
+            expected
+        ))
+    }
+}
+
+// Writes values into a ROM image.  Writes are bounds checked so a bad
+// pointer is an error rather than a panic.
+pub struct RomWriter<'a> {
+    data: &'a mut [u8],
+}
+
+impl<'a> RomWriter<'a> {
+    pub fn new(data: &'a mut [u8]) -> RomWriter<'a> {
+        RomWriter { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    pub fn write_bytes(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        let len = self.data.len();
+        self.data
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_else(|| {
+                format_err!(
+                    "write of {} bytes at {:x} is past the end of the {:x} byte ROM",
+                    bytes.len(),
+                    offset,
+                    len
+                )
+            })?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, offset: usize, val: u8) -> Result<(), Error> {
+        self.write_bytes(offset, &[val])
+    }
+
+    pub fn write_u16(&mut self, offset: usize, val: u16) -> Result<(), Error> {
+        self.write_bytes(offset, &val.to_le_bytes())
+    }
+
+    // Reads back a little endian word, for following pointers while
+    // writing.
+    pub fn read_u16(&self, offset: usize) -> Result<u16, Error> {
+        read_u16(self.data, offset)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,4 +115,16 @@ mod tests {
         assert_eq!(rdata, [0x4, 0x5, 0x6, 0x7]);
         assert_eq!(r.cur_address(), 0x8);
     }
+
+    #[test]
+    fn rom_writer() {
+        let mut data = [0u8; 8];
+        let mut w = RomWriter::new(&mut data);
+        w.write_u16(2, 0x1234).unwrap();
+        w.write_u8(7, 0xff).unwrap();
+        assert_eq!(w.read_u16(2).unwrap(), 0x1234);
+        assert!(w.write_u16(7, 0).is_err());
+        assert!(w.read_u16(7).is_err());
+        assert_eq!(data, [0, 0, 0x34, 0x12, 0, 0, 0, 0xff]);
+    }
 }
@@ -0,0 +1,162 @@
+use serde::Serialize;
+use std::fmt;
+
+use super::SuperMetroidData;
+
+// Rooms are made of 16x16 block screens.
+const SCREEN_BLOCKS: usize = 16 * 16;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum LevelDataMismatchReason {
+    // The level data is also used by another room state whose size it
+    // matches.
+    SharedWithDifferentSize { room_ptr: u16, state: usize },
+    // The level data has more blocks than the room uses.  The extra blocks
+    // are never displayed.
+    TrailingData,
+    // The level data has fewer blocks than the room covers.
+    Truncated,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LevelDataMismatch {
+    pub room_ptr: u16,
+    pub state: usize,
+    pub level_data: u32,
+    pub expected_blocks: usize,
+    pub actual_blocks: usize,
+    pub reason: LevelDataMismatchReason,
+}
+
+impl fmt::Display for LevelDataMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "room {:04x} state {}: level data {:06x} has {} blocks, room needs {}: ",
+            self.room_ptr, self.state, self.level_data, self.actual_blocks, self.expected_blocks
+        )?;
+        match &self.reason {
+            LevelDataMismatchReason::SharedWithDifferentSize { room_ptr, state } => write!(
+                f,
+                "level data is shared with room {:04x} state {} which it fits",
+                room_ptr, state
+            ),
+            LevelDataMismatchReason::TrailingData => write!(
+                f,
+                "{} trailing blocks are never displayed",
+                self.actual_blocks - self.expected_blocks
+            ),
+            LevelDataMismatchReason::Truncated => write!(
+                f,
+                "{} blocks are missing",
+                self.expected_blocks - self.actual_blocks
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub level_data: Vec<LevelDataMismatch>,
+}
+
+impl ValidationReport {
+    pub fn new(sm: &SuperMetroidData) -> ValidationReport {
+        // (level data ptr, room ptr, state index, blocks needed)
+        let mut users: Vec<(u32, u16, usize, usize)> = Vec::new();
+        for room_ptr in &sm.room_order {
+            let mdb = &sm.room_mdb[room_ptr];
+            let expected = mdb.width as usize * mdb.height as usize * SCREEN_BLOCKS;
+            for (i, state) in mdb.states.iter().enumerate() {
+                users.push((state.data.level_data, *room_ptr, i, expected));
+            }
+        }
+
+        let mut level_data = Vec::new();
+        for (ptr, room_ptr, state, expected) in &users {
+            let actual = match sm.level_data.get(ptr) {
+                Some(data) => data.layer_1.len(),
+                None => continue,
+            };
+            if actual == *expected {
+                continue;
+            }
+
+            let owner = users.iter().find(|(other_ptr, _, _, other_expected)| {
+                other_ptr == ptr && *other_expected == actual
+            });
+            let reason = match owner {
+                Some((_, owner_room, owner_state, _)) => {
+                    LevelDataMismatchReason::SharedWithDifferentSize {
+                        room_ptr: *owner_room,
+                        state: *owner_state,
+                    }
+                }
+                None if actual > *expected => LevelDataMismatchReason::TrailingData,
+                None => LevelDataMismatchReason::Truncated,
+            };
+
+            level_data.push(LevelDataMismatch {
+                room_ptr: *room_ptr,
+                state: *state,
+                level_data: *ptr,
+                expected_blocks: *expected,
+                actual_blocks: actual,
+                reason,
+            });
+        }
+
+        ValidationReport { level_data }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.level_data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::add_room;
+    use super::*;
+
+    #[test]
+    fn level_data_mismatches_are_explained() {
+        let mut sm = SuperMetroidData::default();
+        add_room(&mut sm, 0x91f8, 2, &[], vec![]);
+        // Room b is one screen wide but uses room a's level data.
+        let level_data = sm.room_mdb[&0x91f8].states[0].data.level_data;
+        add_room(&mut sm, 0x92fd, 1, &[], vec![]).states[0]
+            .data
+            .level_data = level_data;
+        // Room c has a screen more data than it needs and room d a screen
+        // less.
+        add_room(&mut sm, 0x93aa, 2, &[], vec![]).width = 1;
+        add_room(&mut sm, 0x94cc, 1, &[], vec![]).width = 2;
+
+        let report = ValidationReport::new(&sm);
+        let reasons: Vec<(u16, &LevelDataMismatchReason)> = report
+            .level_data
+            .iter()
+            .map(|m| (m.room_ptr, &m.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (
+                    0x92fd,
+                    &LevelDataMismatchReason::SharedWithDifferentSize {
+                        room_ptr: 0x91f8,
+                        state: 0
+                    }
+                ),
+                (0x93aa, &LevelDataMismatchReason::TrailingData),
+                (0x94cc, &LevelDataMismatchReason::Truncated),
+            ]
+        );
+        assert_eq!(
+            report.level_data[1].to_string(),
+            "room 93aa state 0: level data c293aa has 512 blocks, room needs 256: \
+             256 trailing blocks are never displayed"
+        );
+    }
+}
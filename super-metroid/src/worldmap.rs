@@ -0,0 +1,265 @@
+use failure::{format_err, Error};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use super::buffer::RgbaBuffer;
+use super::doors::{door_blocks, DoorConnection, DoorMap};
+use super::graphics::TileRenderer;
+use super::{Area, SuperMetroidData};
+
+// Each map cell is one 256x256 pixel screen.
+const SCREEN_PIXELS: usize = 16 * 16;
+const BLOCK_PIXELS: usize = 16;
+
+pub struct WorldMapOptions {
+    // Draw a line between each pair of connected doors.
+    pub draw_doors: bool,
+    pub door_color: [u8; 4],
+}
+
+impl Default for WorldMapOptions {
+    fn default() -> WorldMapOptions {
+        WorldMapOptions {
+            draw_doors: false,
+            door_color: [0xff, 0x00, 0xff, 0xff],
+        }
+    }
+}
+
+// Draws a line with Bresenham's algorithm, clipped to the image.
+fn draw_line(img: &mut RgbaBuffer, from: (i64, i64), to: (i64, i64), color: [u8; 4]) {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < img.width && (y as usize) < img.height {
+            img.put_pixel(x as usize, y as usize, color);
+        }
+        if (x, y) == to {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+// Returns a line between the centers of each pair of connected doors of the
+// rooms in `origins`, which holds each room's pixel origin on the canvas.
+// Each pair has one line.
+fn door_lines(
+    sm: &SuperMetroidData,
+    origins: &HashMap<u16, (i64, i64)>,
+) -> Vec<((i64, i64), (i64, i64))> {
+    let door_map = DoorMap::new(sm);
+    let blocks: HashMap<u16, HashMap<usize, Vec<(usize, usize)>>> = origins
+        .keys()
+        .map(|ptr| (*ptr, door_blocks(sm, &sm.room_mdb[ptr])))
+        .collect();
+    // Center of the first door block of a door, in canvas pixels.
+    let door_point = |room_ptr: u16, door_index: usize| -> Option<(i64, i64)> {
+        let (x, y) = *blocks.get(&room_ptr)?.get(&door_index)?.first()?;
+        let origin = origins.get(&room_ptr)?;
+        Some((
+            origin.0 + (x * BLOCK_PIXELS + BLOCK_PIXELS / 2) as i64,
+            origin.1 + (y * BLOCK_PIXELS + BLOCK_PIXELS / 2) as i64,
+        ))
+    };
+
+    let mut lines = Vec::new();
+    for link in door_map.links.values() {
+        let reverse = match link.connection {
+            DoorConnection::Paired { reverse } => reverse,
+            _ => continue,
+        };
+        // Each pair is drawn once.
+        if reverse < link.door {
+            continue;
+        }
+        let from = door_point(link.door.room_ptr, link.door.door_index);
+        let to = door_point(reverse.room_ptr, reverse.door_index);
+        if let (Some(from), Some(to)) = (from, to) {
+            lines.push((from, to));
+        }
+    }
+    lines
+}
+
+// Renders every room of an area in its default state, placed by the rooms'
+// map coordinates.  The image covers the bounding box of the area's rooms.
+pub fn render_area(
+    sm: &SuperMetroidData,
+    area: Area,
+    options: &WorldMapOptions,
+) -> Result<RgbaBuffer, Error> {
+    let rooms: Vec<u16> = sm
+        .room_order
+        .iter()
+        .filter(|ptr| sm.room_mdb[ptr].area == area)
+        .cloned()
+        .collect();
+    if rooms.is_empty() {
+        return Err(format_err!("no rooms in {:?}", area));
+    }
+
+    let min_x = rooms.iter().map(|p| sm.room_mdb[p].x).min().unwrap() as usize;
+    let min_y = rooms.iter().map(|p| sm.room_mdb[p].y).min().unwrap() as usize;
+    let max_x = rooms
+        .iter()
+        .map(|p| sm.room_mdb[p].x as usize + sm.room_mdb[p].width as usize)
+        .max()
+        .unwrap();
+    let max_y = rooms
+        .iter()
+        .map(|p| sm.room_mdb[p].y as usize + sm.room_mdb[p].height as usize)
+        .max()
+        .unwrap();
+
+    let mut img = RgbaBuffer::new(
+        (max_x - min_x) * SCREEN_PIXELS,
+        (max_y - min_y) * SCREEN_PIXELS,
+    );
+
+    // Pixel origin of each room on the canvas.
+    let mut origins = HashMap::new();
+    let mut renderers = HashMap::new();
+    for ptr in &rooms {
+        let mdb = &sm.room_mdb[ptr];
+        let state = &mdb.states[mdb.default_state()];
+        let data = sm
+            .level_data
+            .get(&state.data.level_data)
+            .ok_or_else(|| format_err!("level data for room {:04x} not loaded", ptr))?;
+        let tile_set = state.data.tile_set;
+        let renderer = match renderers.entry(tile_set) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(TileRenderer::for_tile_set(sm, tile_set)?),
+        };
+        // Rooms with palette FX are drawn in their representative colors.
+        let palette_fx = match sm.fx_for(&state.data, None) {
+            Some(fx) => sm.palette_fx_for(tile_set, fx),
+            None => Vec::new(),
+        };
+        renderer.apply_palette_fx(&palette_fx, None);
+        let room_img = renderer.render_room(mdb.default_state(), mdb, data)?;
+
+        let x = (mdb.x as usize - min_x) * SCREEN_PIXELS;
+        let y = (mdb.y as usize - min_y) * SCREEN_PIXELS;
+        img.overlay(&room_img, x, y);
+        origins.insert(*ptr, (x as i64, y as i64));
+    }
+
+    if options.draw_doors {
+        for (from, to) in door_lines(sm, &origins) {
+            draw_line(&mut img, from, to, options.door_color);
+        }
+    }
+
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::graphics::BYTES_PER_TILE;
+    use super::super::test_util::{add_room, door};
+    use super::super::*;
+    use super::*;
+
+    #[test]
+    fn rooms_are_placed_by_map_coordinates() {
+        let mut sm = SuperMetroidData::default();
+        // Every block is one tile of color 1.
+        let red = Color {
+            r: 0xf8,
+            g: 0x00,
+            b: 0x00,
+        };
+        sm.tile_sets = (0..=TileSet::UpperCrateria as usize)
+            .map(|_| TileSetEntry {
+                tile_table_ptr: 0xc1_0000,
+                tiles_ptr: 0xc2_0000,
+                palette_ptr: 0xc3_0000,
+            })
+            .collect();
+        sm.tiles.insert(
+            crate::rom_addr_to_snes!(rommap::CRE_TILES),
+            Tiles { data: Vec::new() },
+        );
+        sm.tiles.insert(
+            0xc2_0000,
+            Tiles {
+                data: vec![0x11; BYTES_PER_TILE],
+            },
+        );
+        sm.tile_tables.insert(
+            crate::rom_addr_to_snes!(rommap::CRE_TILE_TABLE),
+            TileTable {
+                entries: Vec::new(),
+            },
+        );
+        sm.tile_tables.insert(
+            0xc1_0000,
+            TileTable {
+                entries: (0..4).map(|_| TileTableEntry::from_u16(0)).collect(),
+            },
+        );
+        let mut colors = vec![Color { r: 0, g: 0, b: 0 }; 16];
+        colors[1] = red.clone();
+        sm.palettes.insert(0xc3_0000, Palette { colors });
+
+        // Room a at (2, 1) and room b at (3, 2) connect through their first
+        // doors.  Room c is in another area.
+        add_room(&mut sm, 0x91f8, 1, &[(0, 0)], vec![door(0x92fd, 0, 0)]);
+        add_room(&mut sm, 0x92fd, 1, &[(0, 0)], vec![door(0x91f8, 0, 0)]);
+        add_room(&mut sm, 0x93aa, 1, &[], vec![]);
+        for (ptr, x, y) in &[(0x91f8, 2, 1), (0x92fd, 3, 2), (0x93aa, 40, 30)] {
+            let mdb = sm.room_mdb.get_mut(ptr).unwrap();
+            mdb.x = *x;
+            mdb.y = *y;
+        }
+        sm.room_mdb.get_mut(&0x93aa).unwrap().area = Area::Brinstar;
+
+        let options = WorldMapOptions {
+            draw_doors: true,
+            ..WorldMapOptions::default()
+        };
+        let img = render_area(&sm, Area::Crateria, &options).unwrap();
+        let opaque_red = [0xf8, 0x00, 0x00, 0xff];
+        assert_eq!((img.width, img.height), (512, 512));
+        assert_eq!(img.get_pixel(0, 0), opaque_red);
+        assert_eq!(img.get_pixel(255, 255), opaque_red);
+        assert_eq!(img.get_pixel(256, 256), opaque_red);
+        // Neither room covers the other two screens.
+        assert_eq!(img.get_pixel(256, 0), buffer::TRANSPARENT);
+        assert_eq!(img.get_pixel(0, 256), buffer::TRANSPARENT);
+
+        // Door blocks of add_room's door 0 are at block (0, 4).
+        let mut origins = HashMap::new();
+        origins.insert(0x91f8, (0, 0));
+        origins.insert(0x92fd, (256, 256));
+        assert_eq!(door_lines(&sm, &origins), vec![((8, 72), (264, 328))]);
+        assert_eq!(img.get_pixel(8, 72), options.door_color);
+        assert_eq!(img.get_pixel(136, 200), options.door_color);
+        assert!(render_area(&sm, Area::Norfair, &options).is_err());
+    }
+
+    #[test]
+    fn lines_are_clipped() {
+        let mut img = RgbaBuffer::new(4, 4);
+        let color = [1, 2, 3, 4];
+        draw_line(&mut img, (-2, -2), (5, 5), color);
+        for i in 0..4 {
+            assert_eq!(img.get_pixel(i, i), color);
+        }
+        assert_eq!(img.get_pixel(1, 0), [0, 0, 0, 0]);
+    }
+}